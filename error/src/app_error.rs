@@ -1,15 +1,129 @@
-use axum::http::StatusCode;
+use crate::envelope::{error_response, internal_errors_hidden};
+use axum::http::{header, HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
+use prisma_client_rust::{
+  prisma_errors::{
+    common::{ConnectionClosed, DatabaseNotReachable, DatabaseOperationTimeout},
+    query_engine::{PoolTimeout, RecordRequiredButNotFound, UniqueKeyViolation},
+  },
+  QueryError,
+};
 
-pub struct AppError(anyhow::Error);
+// Seconds clients are asked to wait when the database cannot be reached.
+const DATABASE_RETRY_AFTER_SECONDS: u64 = 5;
+
+pub struct AppError {
+  status: StatusCode,
+  code: Option<&'static str>,
+  retry_after: Option<u64>,
+  error: anyhow::Error,
+}
+
+impl AppError {
+  pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+    Self {
+      status,
+      code: None,
+      retry_after: None,
+      error: anyhow::Error::msg(message.into()),
+    }
+  }
+
+  pub fn bad_request(message: impl Into<String>) -> Self {
+    Self::new(StatusCode::BAD_REQUEST, message)
+  }
+
+  pub fn forbidden(message: impl Into<String>) -> Self {
+    Self::new(StatusCode::FORBIDDEN, message)
+  }
+
+  pub fn not_found(message: impl Into<String>) -> Self {
+    Self::new(StatusCode::NOT_FOUND, message)
+  }
+
+  pub fn conflict(message: impl Into<String>) -> Self {
+    Self::new(StatusCode::CONFLICT, message)
+  }
+
+  pub fn too_many_requests(retry_after: u64) -> Self {
+    Self::new(StatusCode::TOO_MANY_REQUESTS, "Too many requests").with_retry_after(retry_after)
+  }
+
+  // Overrides the code derived from the status when clients need to tell errors apart.
+  pub fn with_code(mut self, code: &'static str) -> Self {
+    self.code = Some(code);
+    self
+  }
+
+  pub fn with_retry_after(mut self, seconds: u64) -> Self {
+    self.retry_after = Some(seconds);
+    self
+  }
+}
+
+fn default_code(status: StatusCode) -> &'static str {
+  match status {
+    StatusCode::BAD_REQUEST => "bad_request",
+    StatusCode::UNAUTHORIZED => "unauthorized",
+    StatusCode::FORBIDDEN => "forbidden",
+    StatusCode::NOT_FOUND => "not_found",
+    StatusCode::CONFLICT => "conflict",
+    StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+    StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+    StatusCode::SERVICE_UNAVAILABLE => "service_unavailable",
+    status if status.is_server_error() => "internal_error",
+    _ => "request_failed",
+  }
+}
+
+// Errors of the query engine that are caused by the request rather than by a bug.
+fn query_error_status(err: &QueryError) -> Option<(StatusCode, &'static str)> {
+  if err.is_prisma_error::<UniqueKeyViolation>() {
+    Some((StatusCode::CONFLICT, "Resource already exists"))
+  } else if err.is_prisma_error::<RecordRequiredButNotFound>() {
+    Some((StatusCode::NOT_FOUND, "Resource not found"))
+  } else if err.is_prisma_error::<DatabaseNotReachable>()
+    || err.is_prisma_error::<DatabaseOperationTimeout>()
+    || err.is_prisma_error::<ConnectionClosed>()
+    || err.is_prisma_error::<PoolTimeout>()
+  {
+    Some((StatusCode::SERVICE_UNAVAILABLE, "Database is unavailable"))
+  } else {
+    None
+  }
+}
 
 impl IntoResponse for AppError {
   fn into_response(self) -> Response {
-    (
-      StatusCode::INTERNAL_SERVER_ERROR,
-      format!("Internal server error: {}", self.0),
-    )
-      .into_response()
+    let code = self.code.unwrap_or_else(|| default_code(self.status));
+
+    let message = if self.status.is_server_error() {
+      tracing::error!(
+        status = %self.status,
+        code,
+        error = %format_args!("{:#}", self.error),
+        "request failed"
+      );
+
+      match (internal_errors_hidden(), self.status) {
+        // Only the outermost context, e.g. "Database is unavailable", is meant for clients.
+        (true, StatusCode::SERVICE_UNAVAILABLE) => self.error.to_string(),
+        (true, _) => "Internal server error".to_string(),
+        (false, _) => format!("Internal server error: {:#}", self.error),
+      }
+    } else {
+      self.error.to_string()
+    };
+
+    let mut response = error_response(self.status, code, message, None);
+
+    if let Some(retry_after) = self.retry_after {
+      response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+    }
+
+    response
   }
 }
 
@@ -25,6 +139,25 @@ where
   E: Into<anyhow::Error>,
 {
   fn from(err: E) -> Self {
-    Self(err.into())
+    let error = err.into();
+
+    match error
+      .downcast_ref::<QueryError>()
+      .and_then(query_error_status)
+    {
+      Some((status, message)) if status.is_server_error() => Self {
+        status,
+        code: None,
+        retry_after: Some(DATABASE_RETRY_AFTER_SECONDS),
+        error: error.context(message),
+      },
+      Some((status, message)) => Self::new(status, message),
+      None => Self {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        code: None,
+        retry_after: None,
+        error,
+      },
+    }
   }
 }
@@ -1,28 +1,49 @@
+use crate::envelope::error_response;
 use axum::{
   http::StatusCode,
   response::{IntoResponse, Response},
-  Json,
 };
-use serde_json::json;
 
 pub enum AuthError {
   WrongCredentials,
   MissingCredentials,
   ExpriedCredentials,
   WrongSignature,
+  PermissionDenied,
 }
 
 impl IntoResponse for AuthError {
   fn into_response(self) -> Response {
-    let (status, error_message) = match self {
-      AuthError::WrongCredentials => (StatusCode::UNAUTHORIZED, "Wrong credentials"),
-      AuthError::MissingCredentials => (StatusCode::BAD_REQUEST, "Missing credentials"),
-      AuthError::ExpriedCredentials => (StatusCode::UNAUTHORIZED, "Expried credentials"),
-      AuthError::WrongSignature => (StatusCode::UNAUTHORIZED, "Invalid signature"),
+    let (status, code, error_message) = match self {
+      AuthError::WrongCredentials => (
+        StatusCode::UNAUTHORIZED,
+        "wrong_credentials",
+        "Wrong credentials",
+      ),
+      AuthError::MissingCredentials => (
+        StatusCode::BAD_REQUEST,
+        "missing_credentials",
+        "Missing credentials",
+      ),
+      AuthError::ExpriedCredentials => (
+        StatusCode::UNAUTHORIZED,
+        "expired_credentials",
+        "Expried credentials",
+      ),
+      AuthError::WrongSignature => (
+        StatusCode::UNAUTHORIZED,
+        "invalid_signature",
+        "Invalid signature",
+      ),
+      AuthError::PermissionDenied => (
+        StatusCode::FORBIDDEN,
+        "permission_denied",
+        "Permission denied",
+      ),
     };
-    let body = Json(json!({
-        "error": error_message,
-    }));
-    (status, body).into_response()
+
+    metrics::increment_counter!("auth_outcomes_total", "outcome" => code);
+
+    error_response(status, code, error_message, None)
   }
 }
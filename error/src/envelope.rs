@@ -0,0 +1,57 @@
+use axum::{
+  http::StatusCode,
+  response::{IntoResponse, Response},
+  Json,
+};
+use serde_json::{json, Value};
+use std::{
+  future::Future,
+  sync::atomic::{AtomicBool, Ordering},
+};
+
+tokio::task_local! {
+  static REQUEST_ID: String;
+}
+
+static HIDE_INTERNAL_ERRORS: AtomicBool = AtomicBool::new(false);
+
+// In production the detail of 5xx errors is only logged, clients get a generic message.
+pub fn hide_internal_errors(hide: bool) {
+  HIDE_INTERNAL_ERRORS.store(hide, Ordering::Relaxed);
+}
+
+pub(crate) fn internal_errors_hidden() -> bool {
+  HIDE_INTERNAL_ERRORS.load(Ordering::Relaxed)
+}
+
+// Runs `future` with `request_id` attached so that every error it returns can be traced back.
+pub async fn with_request_id<F: Future>(request_id: String, future: F) -> F::Output {
+  REQUEST_ID.scope(request_id, future).await
+}
+
+pub fn current_request_id() -> Option<String> {
+  REQUEST_ID.try_with(|request_id| request_id.to_owned()).ok()
+}
+
+// Every error leaves the api as `{"error": {"code", "message", "details"?, "requestId"?}}`.
+pub(crate) fn error_response(
+  status: StatusCode,
+  code: &str,
+  message: impl Into<String>,
+  details: Option<Value>,
+) -> Response {
+  let mut error = json!({
+    "code": code,
+    "message": message.into(),
+  });
+
+  if let Some(details) = details {
+    error["details"] = details;
+  }
+
+  if let Some(request_id) = current_request_id() {
+    error["requestId"] = json!(request_id);
+  }
+
+  (status, Json(json!({ "error": error }))).into_response()
+}
@@ -1,7 +1,9 @@
 mod app_error;
 mod auth_error;
+mod envelope;
 mod validate_error;
 
 pub use app_error::*;
 pub use auth_error::*;
+pub use envelope::{current_request_id, hide_internal_errors, with_request_id};
 pub use validate_error::*;
@@ -1,21 +1,84 @@
+use crate::envelope::error_response;
 use axum::{
   http::StatusCode,
   response::{IntoResponse, Response},
 };
+use serde_json::{json, Map, Value};
+use validator::{ValidationErrors, ValidationErrorsKind};
 
-pub struct ValidateError(String);
+pub enum ValidateError {
+  InvalidJson,
+  InvalidQuery,
+  Fields(ValidationErrors),
+}
+
+impl From<ValidationErrors> for ValidateError {
+  fn from(errors: ValidationErrors) -> Self {
+    Self::Fields(errors)
+  }
+}
+
+// Flattens nested structs and lists into paths such as `allocations[0].amount`.
+fn collect_field_errors(errors: &ValidationErrors, prefix: &str, fields: &mut Map<String, Value>) {
+  for (field, kind) in errors.errors() {
+    let path = if prefix.is_empty() {
+      field.to_string()
+    } else {
+      format!("{prefix}.{field}")
+    };
 
-impl<E> From<E> for ValidateError
-where
-  E: Into<String>,
-{
-  fn from(err: E) -> Self {
-    Self(err.into())
+    match kind {
+      ValidationErrorsKind::Field(errors) => {
+        fields.insert(
+          path,
+          errors
+            .iter()
+            .map(|error| {
+              json!({
+                "code": error.code,
+                "message": error.message,
+                "params": error.params,
+              })
+            })
+            .collect(),
+        );
+      }
+      ValidationErrorsKind::Struct(errors) => collect_field_errors(errors, &path, fields),
+      ValidationErrorsKind::List(items) => {
+        for (index, errors) in items {
+          collect_field_errors(errors, &format!("{path}[{index}]"), fields);
+        }
+      }
+    }
   }
 }
 
 impl IntoResponse for ValidateError {
   fn into_response(self) -> Response {
-    (StatusCode::BAD_REQUEST, self.0).into_response()
+    match self {
+      ValidateError::InvalidJson => error_response(
+        StatusCode::BAD_REQUEST,
+        "invalid_json",
+        "Invalid json body",
+        None,
+      ),
+      ValidateError::InvalidQuery => error_response(
+        StatusCode::BAD_REQUEST,
+        "invalid_query",
+        "Invalid query string",
+        None,
+      ),
+      ValidateError::Fields(errors) => {
+        let mut fields = Map::new();
+        collect_field_errors(&errors, "", &mut fields);
+
+        error_response(
+          StatusCode::BAD_REQUEST,
+          "validation_failed",
+          "Some fields are invalid",
+          Some(json!({ "fields": fields })),
+        )
+      }
+    }
   }
 }
@@ -0,0 +1,19 @@
+use axum_baby::open_api::ApiDoc;
+use std::{env, fs, process};
+use utoipa::OpenApi;
+
+// Writes the OpenAPI document to the given path, `openapi.json` by default.
+fn main() {
+  let path = env::args().nth(1).unwrap_or_else(|| "openapi.json".to_string());
+
+  let spec = ApiDoc::openapi()
+    .to_pretty_json()
+    .expect("serializing the openapi document was wrong");
+
+  if let Err(err) = fs::write(&path, spec + "\n") {
+    eprintln!("writing {path} failed: {err}");
+    process::exit(1);
+  }
+
+  println!("wrote {path}");
+}
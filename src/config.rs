@@ -0,0 +1,708 @@
+use crate::intercept::cors::OriginPattern;
+use crate::schedulers::ProcessMode;
+use anyhow::{bail, Context, Result};
+use axum::http::HeaderName;
+use cron::Schedule;
+use ethers::{signers::LocalWallet, types::Address};
+use std::{
+  collections::HashMap, env, fmt::Display, net::SocketAddr, path::PathBuf, str::FromStr,
+  time::Duration,
+};
+use tracing_subscriber::EnvFilter;
+
+const MARKET_DATA_PROVIDERS: [&str; 3] = ["coinmarketcap", "coingecko", "fixture"];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum AppEnv {
+  Development,
+  Production,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+  Json,
+  Pretty,
+}
+
+impl FromStr for LogFormat {
+  type Err = anyhow::Error;
+
+  fn from_str(value: &str) -> Result<Self> {
+    match value {
+      "json" => Ok(Self::Json),
+      "pretty" => Ok(Self::Pretty),
+      value => bail!("unknown log format {value}"),
+    }
+  }
+}
+
+impl FromStr for AppEnv {
+  type Err = anyhow::Error;
+
+  fn from_str(value: &str) -> Result<Self> {
+    match value {
+      "development" => Ok(Self::Development),
+      "production" => Ok(Self::Production),
+      value => bail!("unknown app env {value}"),
+    }
+  }
+}
+
+// Everything the process reads from its environment, loaded and validated once at startup.
+pub struct Config {
+  pub app_env: AppEnv,
+  // Only when `APP_ENV=development` is set explicitly, a forgotten `APP_ENV` must not leak internals.
+  pub expose_internal_errors: bool,
+  pub log_format: LogFormat,
+  // `RUST_LOG` style directives, e.g. `info,axum_baby=debug`.
+  pub log_filter: String,
+  pub process_mode: ProcessMode,
+  pub bind_addr: SocketAddr,
+  // Waited for in-flight requests after a shutdown signal, and then again for running jobs.
+  pub shutdown_timeout: Duration,
+  pub database_url: String,
+  pub redis_url: String,
+  pub cors: CorsConfig,
+  pub auth: AuthConfig,
+  pub rate_limit: RateLimitConfig,
+  // Cron expressions keyed by job name, overriding the default schedule of the job.
+  pub job_schedules: HashMap<String, String>,
+  pub storage: StorageConfig,
+  pub market_data: MarketDataConfig,
+  pub voucher: Option<VoucherConfig>,
+  pub twitter: Option<OAuthClientConfig>,
+  pub discord: Option<OAuthClientConfig>,
+  pub telegram_bot_token: Option<String>,
+  // Required as a bearer token on `/metrics` when set.
+  pub metrics_token: Option<String>,
+}
+
+pub struct AuthConfig {
+  // Host the SIWE messages are signed for, e.g. `app.example.com`.
+  pub siwe_domain: String,
+  pub jwt_secret: String,
+  pub jwt_refresh_secret: String,
+  pub access_token_ttl: chrono::Duration,
+  pub refresh_token_ttl: chrono::Duration,
+}
+
+// The admin routes are served to a different set of origins than the public ones.
+pub struct CorsConfig {
+  pub public: CorsPolicy,
+  pub admin: CorsPolicy,
+}
+
+pub struct CorsPolicy {
+  // Any origin is allowed when empty.
+  pub origins: Vec<OriginPattern>,
+  pub allow_credentials: bool,
+  pub expose_headers: Vec<HeaderName>,
+  pub max_age: Duration,
+}
+
+// `None` disables the limit of a route group.
+pub struct RateLimitConfig {
+  // Takes the client IP from `X-Forwarded-For`, only safe behind a load balancer that appends to it.
+  pub trust_forwarded_for: bool,
+  pub auth: Option<RateLimitPolicy>,
+  pub public: Option<RateLimitPolicy>,
+  pub user: Option<RateLimitPolicy>,
+  pub admin: Option<RateLimitPolicy>,
+}
+
+// At most `limit` requests in any `window`, written as `<limit>/<seconds>`, e.g. `20/60`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimitPolicy {
+  pub limit: u32,
+  pub window: Duration,
+}
+
+impl FromStr for RateLimitPolicy {
+  type Err = anyhow::Error;
+
+  fn from_str(value: &str) -> Result<Self> {
+    let (limit, seconds) = value
+      .split_once('/')
+      .context("expected <requests>/<seconds>")?;
+    let limit: u32 = limit.trim().parse().context("invalid number of requests")?;
+    let seconds: u64 = seconds
+      .trim()
+      .parse()
+      .context("invalid number of seconds")?;
+
+    if limit == 0 || seconds == 0 {
+      bail!("requests and seconds must be positive");
+    }
+
+    Ok(Self {
+      limit,
+      window: Duration::from_secs(seconds),
+    })
+  }
+}
+
+pub enum StorageConfig {
+  Local(LocalStorageConfig),
+  S3(S3Config),
+}
+
+pub struct LocalStorageConfig {
+  pub root: PathBuf,
+  pub public_url: String,
+  // Path of `public_url`, the files under `root` are served from it.
+  pub url_path: String,
+}
+
+pub struct S3Config {
+  pub endpoint: String,
+  pub region: String,
+  pub bucket: String,
+  pub access_key: String,
+  pub secret_key: String,
+  pub public_url: String,
+}
+
+pub struct MarketDataConfig {
+  pub provider: String,
+  pub fallback: Option<String>,
+  pub cmc: CmcConfig,
+  pub coingecko: CoinGeckoConfig,
+  pub fixture_path: PathBuf,
+}
+
+pub struct CmcConfig {
+  pub base_url: String,
+  pub api_key: Option<String>,
+  pub daily_credit_budget: i64,
+  pub rate_limit_per_minute: u32,
+}
+
+pub struct CoinGeckoConfig {
+  pub base_url: String,
+  pub api_key: Option<String>,
+  pub rate_limit_per_minute: u32,
+}
+
+pub struct VoucherConfig {
+  pub rpc_url: String,
+  pub chain_id: u64,
+  pub contract: Address,
+  pub signer_key: String,
+}
+
+pub struct OAuthClientConfig {
+  pub client_id: String,
+  pub client_secret: Option<String>,
+  pub redirect_uri: String,
+  pub authorize_url: Option<String>,
+  pub token_url: Option<String>,
+  pub profile_url: Option<String>,
+}
+
+// Reads variables while collecting every problem, so that a bad deployment reports all of them at once.
+struct Vars {
+  values: HashMap<String, String>,
+  errors: Vec<String>,
+}
+
+impl Vars {
+  fn optional(&self, name: &str) -> Option<String> {
+    self
+      .values
+      .get(name)
+      .map(|value| value.trim().to_string())
+      .filter(|value| !value.is_empty())
+  }
+
+  fn or(&self, name: &str, default: &str) -> String {
+    self.optional(name).unwrap_or_else(|| default.to_string())
+  }
+
+  fn required(&mut self, name: &str) -> String {
+    self.optional(name).unwrap_or_else(|| {
+      self.errors.push(format!("{name} is not set"));
+      String::new()
+    })
+  }
+
+  fn parse<T>(&mut self, name: &str, default: T) -> T
+  where
+    T: FromStr,
+    T::Err: Display,
+  {
+    match self.optional(name) {
+      Some(value) => self.parse_value(name, &value).unwrap_or(default),
+      None => default,
+    }
+  }
+
+  fn parse_required<T>(&mut self, name: &str) -> T
+  where
+    T: FromStr + Default,
+    T::Err: Display,
+  {
+    match self.optional(name) {
+      Some(value) => self.parse_value(name, &value).unwrap_or_default(),
+      None => {
+        self.errors.push(format!("{name} is not set"));
+        T::default()
+      }
+    }
+  }
+
+  fn parse_value<T>(&mut self, name: &str, value: &str) -> Option<T>
+  where
+    T: FromStr,
+    T::Err: Display,
+  {
+    match value.parse() {
+      Ok(parsed) => Some(parsed),
+      Err(err) => {
+        self.errors.push(format!("{name} is invalid: {err}"));
+        None
+      }
+    }
+  }
+
+  fn check(&mut self, valid: bool, message: impl Into<String>) {
+    if !valid {
+      self.errors.push(message.into());
+    }
+  }
+}
+
+impl Config {
+  // Reads the dotenv file at `CONFIG_FILE` (`.env` by default) and then the environment, which takes precedence.
+  pub fn load() -> Result<Self> {
+    match env::var("CONFIG_FILE") {
+      Ok(path) => {
+        dotenv::from_path(&path).with_context(|| format!("reading config file {path} failed"))?;
+      }
+      Err(_) => {
+        dotenv::dotenv().ok();
+      }
+    }
+
+    Self::from_vars(env::vars().collect())
+  }
+
+  pub fn from_vars(values: HashMap<String, String>) -> Result<Self> {
+    let mut vars = Vars {
+      values,
+      errors: vec![],
+    };
+
+    let app_env = vars.parse("APP_ENV", AppEnv::Development);
+
+    let config = Self {
+      app_env,
+      expose_internal_errors: vars.optional("APP_ENV").as_deref() == Some("development"),
+      log_format: vars.parse("LOG_FORMAT", LogFormat::Json),
+      log_filter: log_filter(&mut vars),
+      process_mode: vars.parse("PROCESS_MODE", ProcessMode::All),
+      bind_addr: vars.parse("BIND_ADDR", SocketAddr::from(([0, 0, 0, 0], 8080))),
+      shutdown_timeout: Duration::from_secs(vars.parse("SHUTDOWN_TIMEOUT_SECONDS", 10)),
+      database_url: vars.required("DATABASE_URL"),
+      redis_url: vars.or("REDIS_URL", "redis://127.0.0.1/"),
+      cors: cors(&mut vars, app_env),
+      auth: auth(&mut vars),
+      rate_limit: rate_limit(&mut vars),
+      job_schedules: job_schedules(&mut vars),
+      storage: storage(&mut vars),
+      market_data: market_data(&mut vars),
+      voucher: voucher(&mut vars),
+      twitter: oauth_client(&mut vars, "TWITTER"),
+      discord: oauth_client(&mut vars, "DISCORD"),
+      telegram_bot_token: vars.optional("TELEGRAM_BOT_TOKEN"),
+      metrics_token: vars.optional("METRICS_TOKEN"),
+    };
+
+    if !vars.errors.is_empty() {
+      bail!("invalid configuration:\n  {}", vars.errors.join("\n  "));
+    }
+
+    Ok(config)
+  }
+}
+
+fn log_filter(vars: &mut Vars) -> String {
+  let filter = vars.or("RUST_LOG", "info");
+  vars.parse_value::<EnvFilter>("RUST_LOG", &filter);
+  filter
+}
+
+// `CORS_*` configures the public routes, each `CORS_ADMIN_*` falls back to its public counterpart.
+fn cors(vars: &mut Vars, app_env: AppEnv) -> CorsConfig {
+  let origins = vars.or("CORS_ORIGINS", "*");
+  let expose_headers = vars.or("CORS_EXPOSE_HEADERS", "x-request-id, retry-after");
+
+  let public = CorsPolicy {
+    origins: origin_patterns(vars, "CORS_ORIGINS", &origins),
+    allow_credentials: vars.parse("CORS_ALLOW_CREDENTIALS", false),
+    expose_headers: header_names(vars, "CORS_EXPOSE_HEADERS", &expose_headers),
+    max_age: Duration::from_secs(vars.parse("CORS_MAX_AGE_SECONDS", 600)),
+  };
+
+  let admin_origins = vars.or("CORS_ADMIN_ORIGINS", &origins);
+  let admin_expose_headers = vars.or("CORS_ADMIN_EXPOSE_HEADERS", &expose_headers);
+
+  let admin = CorsPolicy {
+    origins: origin_patterns(vars, "CORS_ADMIN_ORIGINS", &admin_origins),
+    allow_credentials: vars.parse("CORS_ADMIN_ALLOW_CREDENTIALS", public.allow_credentials),
+    expose_headers: header_names(vars, "CORS_ADMIN_EXPOSE_HEADERS", &admin_expose_headers),
+    max_age: Duration::from_secs(
+      vars.parse("CORS_ADMIN_MAX_AGE_SECONDS", public.max_age.as_secs()),
+    ),
+  };
+
+  for (prefix, policy) in [("CORS", &public), ("CORS_ADMIN", &admin)] {
+    // Browsers reject credentialed responses that allow any origin.
+    vars.check(
+      !(policy.origins.is_empty() && policy.allow_credentials),
+      format!("{prefix}_ALLOW_CREDENTIALS requires {prefix}_ORIGINS to list origins"),
+    );
+    vars.check(
+      !(policy.origins.is_empty() && app_env == AppEnv::Production),
+      format!("{prefix}_ORIGINS must list origins in production"),
+    );
+  }
+
+  CorsConfig { public, admin }
+}
+
+// A comma separated list, `*` allows any origin.
+fn origin_patterns(vars: &mut Vars, name: &str, origins: &str) -> Vec<OriginPattern> {
+  if origins == "*" {
+    return vec![];
+  }
+
+  origins
+    .split(',')
+    .map(str::trim)
+    .filter(|origin| !origin.is_empty())
+    .filter_map(|origin| vars.parse_value(name, origin))
+    .collect()
+}
+
+fn header_names(vars: &mut Vars, name: &str, headers: &str) -> Vec<HeaderName> {
+  headers
+    .split(',')
+    .map(str::trim)
+    .filter(|header| !header.is_empty())
+    .filter_map(|header| vars.parse_value(name, header))
+    .collect()
+}
+
+fn auth(vars: &mut Vars) -> AuthConfig {
+  let jwt_secret = vars.required("JWT_SECRET");
+  let jwt_refresh_secret = vars.required("JWT_REFRESH_SECRET");
+  let access_days: i64 = vars.parse("ACCESS_TOKEN_TTL_DAYS", 3);
+  let refresh_days: i64 = vars.parse("REFRESH_TOKEN_TTL_DAYS", 60);
+
+  vars.check(
+    jwt_secret.is_empty() || jwt_secret != jwt_refresh_secret,
+    "JWT_SECRET and JWT_REFRESH_SECRET must differ",
+  );
+  vars.check(access_days > 0, "ACCESS_TOKEN_TTL_DAYS must be positive");
+  vars.check(
+    refresh_days > access_days,
+    "REFRESH_TOKEN_TTL_DAYS must be longer than ACCESS_TOKEN_TTL_DAYS",
+  );
+
+  AuthConfig {
+    siwe_domain: vars.required("SIWE_DOMAIN"),
+    jwt_secret,
+    jwt_refresh_secret,
+    access_token_ttl: chrono::Duration::days(access_days),
+    refresh_token_ttl: chrono::Duration::days(refresh_days),
+  }
+}
+
+fn rate_limit(vars: &mut Vars) -> RateLimitConfig {
+  RateLimitConfig {
+    trust_forwarded_for: vars.parse("TRUST_FORWARDED_FOR", false),
+    auth: rate_limit_policy(vars, "RATE_LIMIT_AUTH", "20/60"),
+    public: rate_limit_policy(vars, "RATE_LIMIT_PUBLIC", "300/60"),
+    user: rate_limit_policy(vars, "RATE_LIMIT_USER", "300/60"),
+    admin: rate_limit_policy(vars, "RATE_LIMIT_ADMIN", "600/60"),
+  }
+}
+
+// `off` disables the limit.
+fn rate_limit_policy(vars: &mut Vars, name: &str, default: &str) -> Option<RateLimitPolicy> {
+  match vars.or(name, default).as_str() {
+    "off" => None,
+    policy => vars.parse_value(name, policy),
+  }
+}
+
+// `JOB_CMC_CRAWL_CRON` overrides the schedule of the `cmc_crawl` job.
+fn job_schedules(vars: &mut Vars) -> HashMap<String, String> {
+  let overrides: Vec<(String, String)> = vars
+    .values
+    .iter()
+    .filter_map(|(name, value)| {
+      let job = name.strip_prefix("JOB_")?.strip_suffix("_CRON")?;
+      Some((job.to_lowercase(), value.trim().to_string()))
+    })
+    .collect();
+
+  for (job, schedule) in &overrides {
+    // The scheduler expects seconds first and an optional year last.
+    if let Err(err) = Schedule::from_str(schedule) {
+      vars.check(
+        false,
+        format!(
+          "JOB_{}_CRON is not a cron expression with seconds first, got `{schedule}`: {err}",
+          job.to_uppercase()
+        ),
+      );
+    }
+  }
+
+  overrides.into_iter().collect()
+}
+
+fn storage(vars: &mut Vars) -> StorageConfig {
+  match vars.or("STORAGE_DRIVER", "local").as_str() {
+    "s3" => {
+      let endpoint = vars.required("S3_ENDPOINT");
+      let bucket = vars.required("S3_BUCKET");
+
+      if !endpoint.is_empty() {
+        vars.parse_value::<surf::Url>("S3_ENDPOINT", &endpoint);
+      }
+
+      StorageConfig::S3(S3Config {
+        public_url: vars
+          .optional("S3_PUBLIC_URL")
+          .unwrap_or_else(|| format!("{}/{bucket}", endpoint.trim_end_matches('/'))),
+        region: vars.or("S3_REGION", "us-east-1"),
+        access_key: vars.required("S3_ACCESS_KEY"),
+        secret_key: vars.required("S3_SECRET_KEY"),
+        endpoint,
+        bucket,
+      })
+    }
+    driver => {
+      vars.check(
+        driver == "local",
+        format!("STORAGE_DRIVER {driver} is unknown"),
+      );
+
+      let public_url = vars.or("LOCAL_STORAGE_URL", "http://localhost:8080/uploads");
+      let url_path = vars
+        .parse_value::<surf::Url>("LOCAL_STORAGE_URL", &public_url)
+        .map(|url| url.path().trim_end_matches('/').to_string())
+        .unwrap_or_default();
+
+      vars.check(
+        !url_path.is_empty(),
+        "LOCAL_STORAGE_URL must have a path to serve the uploads from, e.g. /uploads",
+      );
+
+      StorageConfig::Local(LocalStorageConfig {
+        root: vars.or("LOCAL_STORAGE_DIR", "uploads").into(),
+        public_url,
+        url_path,
+      })
+    }
+  }
+}
+
+fn market_data(vars: &mut Vars) -> MarketDataConfig {
+  let provider = vars.or("MARKET_DATA_PROVIDER", "coinmarketcap");
+  let fallback = vars.optional("MARKET_DATA_FALLBACK");
+
+  let config = MarketDataConfig {
+    cmc: CmcConfig {
+      base_url: vars.or("CMC_BASE_URL", "https://pro-api.coinmarketcap.com"),
+      api_key: vars.optional("CMC_KEY"),
+      daily_credit_budget: vars.parse("CMC_DAILY_CREDIT_BUDGET", 333),
+      rate_limit_per_minute: vars.parse("CMC_RATE_LIMIT_PER_MINUTE", 30),
+    },
+    coingecko: CoinGeckoConfig {
+      base_url: vars.or("COINGECKO_BASE_URL", "https://api.coingecko.com/api/v3"),
+      api_key: vars.optional("COINGECKO_KEY"),
+      rate_limit_per_minute: vars.parse("COINGECKO_RATE_LIMIT_PER_MINUTE", 30),
+    },
+    fixture_path: vars
+      .or("MARKET_DATA_FIXTURE", "fixtures/market_data.json")
+      .into(),
+    provider,
+    fallback,
+  };
+
+  for name in [Some(&config.provider), config.fallback.as_ref()]
+    .into_iter()
+    .flatten()
+  {
+    vars.check(
+      MARKET_DATA_PROVIDERS.contains(&name.as_str()),
+      format!("market data provider {name} is unknown"),
+    );
+    vars.check(
+      name != "coinmarketcap" || config.cmc.api_key.is_some(),
+      "CMC_KEY is not set",
+    );
+  }
+
+  config
+}
+
+// Vouchers are disabled unless `VOUCHER_CONTRACT` is set.
+fn voucher(vars: &mut Vars) -> Option<VoucherConfig> {
+  vars.optional("VOUCHER_CONTRACT")?;
+
+  let signer_key = vars.required("VOUCHER_SIGNER_KEY");
+  if !signer_key.is_empty() {
+    vars.check(
+      signer_key.parse::<LocalWallet>().is_ok(),
+      "VOUCHER_SIGNER_KEY is not a valid private key",
+    );
+  }
+
+  Some(VoucherConfig {
+    rpc_url: vars.required("RPC_URL"),
+    chain_id: vars.parse_required("VOUCHER_CHAIN_ID"),
+    contract: vars.parse_required("VOUCHER_CONTRACT"),
+    signer_key,
+  })
+}
+
+// A platform can only be linked when its `<PREFIX>_CLIENT_ID` is set.
+fn oauth_client(vars: &mut Vars, prefix: &str) -> Option<OAuthClientConfig> {
+  let client_id = vars.optional(&format!("{prefix}_CLIENT_ID"))?;
+
+  Some(OAuthClientConfig {
+    client_id,
+    client_secret: vars.optional(&format!("{prefix}_CLIENT_SECRET")),
+    redirect_uri: vars.required(&format!("{prefix}_REDIRECT_URI")),
+    authorize_url: vars.optional(&format!("{prefix}_AUTHORIZE_URL")),
+    token_url: vars.optional(&format!("{prefix}_TOKEN_URL")),
+    profile_url: vars.optional(&format!("{prefix}_PROFILE_URL")),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs
+      .iter()
+      .map(|(name, value)| (name.to_string(), value.to_string()))
+      .collect()
+  }
+
+  fn minimal() -> Vec<(&'static str, &'static str)> {
+    vec![
+      ("DATABASE_URL", "postgresql://localhost/axum"),
+      ("JWT_SECRET", "access"),
+      ("JWT_REFRESH_SECRET", "refresh"),
+      ("SIWE_DOMAIN", "localhost:3000"),
+      ("CMC_KEY", "key"),
+    ]
+  }
+
+  #[test]
+  fn defaults_match_previous_behavior() {
+    let config = Config::from_vars(vars(&minimal())).unwrap();
+
+    assert_eq!(config.bind_addr.to_string(), "0.0.0.0:8080");
+    assert_eq!(config.redis_url, "redis://127.0.0.1/");
+    assert!(config.cors.public.origins.is_empty());
+    assert!(!config.cors.public.allow_credentials);
+    assert_eq!(config.auth.access_token_ttl, chrono::Duration::days(3));
+    assert_eq!(config.auth.refresh_token_ttl, chrono::Duration::days(60));
+    assert!(config.voucher.is_none());
+  }
+
+  #[test]
+  fn exposes_internal_errors_only_when_development_is_explicit() {
+    let mut pairs = minimal();
+    let config = Config::from_vars(vars(&pairs)).unwrap();
+    assert!(!config.expose_internal_errors);
+
+    pairs.push(("APP_ENV", "development"));
+    let config = Config::from_vars(vars(&pairs)).unwrap();
+    assert!(config.expose_internal_errors);
+  }
+
+  #[test]
+  fn reads_rate_limits() {
+    let mut pairs = minimal();
+    pairs.push(("RATE_LIMIT_AUTH", "5/10"));
+    pairs.push(("RATE_LIMIT_ADMIN", "off"));
+
+    let config = Config::from_vars(vars(&pairs)).unwrap();
+
+    assert_eq!(
+      config.rate_limit.auth,
+      Some(RateLimitPolicy {
+        limit: 5,
+        window: Duration::from_secs(10),
+      })
+    );
+    assert_eq!(config.rate_limit.admin, None);
+    assert!(config.rate_limit.public.is_some());
+
+    pairs.push(("RATE_LIMIT_USER", "0/60"));
+    let err = Config::from_vars(vars(&pairs)).err().unwrap().to_string();
+    assert!(err.contains("RATE_LIMIT_USER is invalid"), "{err}");
+  }
+
+  #[test]
+  fn reports_every_problem() {
+    let err = Config::from_vars(vars(&[
+      ("JWT_SECRET", "same"),
+      ("JWT_REFRESH_SECRET", "same"),
+      ("BIND_ADDR", "localhost"),
+      ("JOB_CMC_CRAWL_CRON", "*/5 * * * *"),
+      ("APP_ENV", "production"),
+    ]))
+    .err()
+    .unwrap()
+    .to_string();
+
+    for expected in [
+      "DATABASE_URL is not set",
+      "BIND_ADDR is invalid",
+      "JWT_SECRET and JWT_REFRESH_SECRET must differ",
+      "JOB_CMC_CRAWL_CRON is not a cron expression with seconds first",
+      "CMC_KEY is not set",
+      "CORS_ORIGINS must list origins in production",
+    ] {
+      assert!(err.contains(expected), "{expected} missing from {err}");
+    }
+  }
+
+  #[test]
+  fn reads_job_schedules_and_cors_policies() {
+    let mut pairs = minimal();
+    pairs.push(("JOB_CMC_CRAWL_CRON", "0 */10 * * * *"));
+    pairs.push((
+      "CORS_ORIGINS",
+      "https://app.example.com, https://*.preview.example.com",
+    ));
+    pairs.push(("CORS_ADMIN_ORIGINS", "https://admin.example.com"));
+    pairs.push(("CORS_ADMIN_ALLOW_CREDENTIALS", "true"));
+
+    let config = Config::from_vars(vars(&pairs)).unwrap();
+
+    assert_eq!(config.job_schedules["cmc_crawl"], "0 */10 * * * *");
+    assert_eq!(config.cors.public.origins.len(), 2);
+    assert!(!config.cors.public.allow_credentials);
+    assert_eq!(config.cors.admin.origins.len(), 1);
+    assert!(config.cors.admin.allow_credentials);
+    assert_eq!(config.cors.admin.expose_headers.len(), 2);
+
+    pairs.push(("CORS_ORIGINS", "*"));
+    pairs.push(("CORS_ALLOW_CREDENTIALS", "true"));
+    let err = Config::from_vars(vars(&pairs)).err().unwrap().to_string();
+    assert!(
+      err.contains("CORS_ALLOW_CREDENTIALS requires CORS_ORIGINS to list origins"),
+      "{err}"
+    );
+  }
+}
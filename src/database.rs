@@ -1,3 +1,6 @@
+pub mod cache;
+pub mod observe;
 #[allow(warnings, unused)]
 pub mod prisma;
 pub mod query_buider;
+pub mod redis_conn;
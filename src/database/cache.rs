@@ -0,0 +1,352 @@
+use super::redis_conn::RedisConn;
+use crate::schedulers::lease::Lease;
+use anyhow::Result;
+use redis::RedisResult;
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use std::{future::Future, time::Duration};
+use tokio::time::Instant;
+
+// Long enough for a slow query, a loader that dies only holds the others back this long.
+const LOAD_LOCK_TTL: Duration = Duration::from_secs(5);
+const LOAD_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// KEYS are the entry followed by the set and the generation of every tag, ARGV the value, its ttl and
+// the generation of every tag when the value was loaded. Nothing is stored when a tag was invalidated
+// in the meantime, the value may predate the change. Otherwise the key is added to the set of every
+// tag, which lives at least as long as its entries.
+const SET_SCRIPT: &str = r#"
+  local tags = (#KEYS - 1) / 2
+  for i = 1, tags do
+    if (redis.call("GET", KEYS[2 * i + 1]) or "0") ~= ARGV[2 + i] then
+      return 0
+    end
+  end
+
+  redis.call("SET", KEYS[1], ARGV[1], "PX", ARGV[2])
+  for i = 1, tags do
+    redis.call("SADD", KEYS[2 * i], KEYS[1])
+    if redis.call("PTTL", KEYS[2 * i]) < tonumber(ARGV[2]) then
+      redis.call("PEXPIRE", KEYS[2 * i], ARGV[2])
+    end
+  end
+  return 1
+"#;
+
+// Bumps the generation first, so that loads already running cannot store what they read before.
+const INVALIDATE_TAG_SCRIPT: &str = r#"
+  redis.call("INCR", KEYS[2])
+  local keys = redis.call("SMEMBERS", KEYS[1])
+  for i = 1, #keys, 500 do
+    redis.call("DEL", unpack(keys, i, math.min(i + 499, #keys)))
+  end
+  redis.call("DEL", KEYS[1])
+  return #keys
+"#;
+
+fn entry_key(key: &str) -> String {
+  format!("cache:{key}")
+}
+
+fn tag_key(tag: &str) -> String {
+  format!("cache:tag:{tag}")
+}
+
+fn generation_key(tag: &str) -> String {
+  format!("cache:tag:{tag}:generation")
+}
+
+// Key of the entry cached for `parts`, e.g. the filters of a request. They are hashed so that values
+// sent by clients neither make the key arbitrarily long nor collide with each other on a separator.
+pub fn hashed_key(prefix: &str, parts: &impl Serialize) -> String {
+  let parts = serde_json::to_vec(parts).unwrap_or_default();
+  format!("{prefix}:{}", hex::encode(Sha256::digest(parts)))
+}
+
+// An entry that no longer deserializes, e.g. after a schema change, is a miss.
+pub async fn get<T: DeserializeOwned>(
+  redis_conn: &mut RedisConn,
+  key: &str,
+) -> RedisResult<Option<T>> {
+  let cached = redis::cmd("GET")
+    .arg(entry_key(key))
+    .query_async::<_, Option<String>>(redis_conn)
+    .await?;
+
+  Ok(cached.and_then(|cached| serde_json::from_str(&cached).ok()))
+}
+
+// Current generation of every tag, to be read before loading the value passed to `set`.
+pub async fn generations(redis_conn: &mut RedisConn, tags: &[&str]) -> RedisResult<Vec<u64>> {
+  if tags.is_empty() {
+    return Ok(vec![]);
+  }
+
+  let generations = redis::cmd("MGET")
+    .arg(
+      tags
+        .iter()
+        .map(|tag| generation_key(tag))
+        .collect::<Vec<String>>(),
+    )
+    .query_async::<_, Vec<Option<u64>>>(redis_conn)
+    .await?;
+
+  Ok(
+    generations
+      .into_iter()
+      .map(Option::unwrap_or_default)
+      .collect(),
+  )
+}
+
+// Returns false when a tag was invalidated since `generations` were read, the value is not stored then.
+pub async fn set<T: Serialize>(
+  redis_conn: &mut RedisConn,
+  key: &str,
+  value: &T,
+  ttl: Duration,
+  tags: &[&str],
+  generations: &[u64],
+) -> Result<bool> {
+  let mut script = redis::Script::new(SET_SCRIPT).prepare_invoke();
+  script.key(entry_key(key));
+
+  for tag in tags {
+    script.key(tag_key(tag)).key(generation_key(tag));
+  }
+
+  script
+    .arg(serde_json::to_string(value)?)
+    .arg(ttl.as_millis().max(1) as u64);
+
+  for generation in generations {
+    script.arg(generation);
+  }
+
+  Ok(script.invoke_async::<_, i64>(redis_conn).await? == 1)
+}
+
+// Drops every entry cached with `tag`, to be called whenever the data behind it changes.
+#[tracing::instrument(skip(redis_conn))]
+pub async fn invalidate_tag(redis_conn: &mut RedisConn, tag: &str) {
+  if let Err(err) = redis::Script::new(INVALIDATE_TAG_SCRIPT)
+    .key(tag_key(tag))
+    .key(generation_key(tag))
+    .invoke_async::<_, i64>(redis_conn)
+    .await
+  {
+    tracing::warn!(tag, error = %err, "invalidating cache tag failed");
+  }
+}
+
+// Returns the cached value of `key`, or loads it and caches it for `ttl(&value)`.
+// Only one caller loads a missing key at a time, the others wait for its result. Redis failures never fail
+// the request, the value is then loaded directly.
+pub async fn get_or_load<T, E, Fut>(
+  redis_conn: &mut RedisConn,
+  key: &str,
+  tags: &[&str],
+  ttl: impl Fn(&T) -> Duration,
+  load: impl FnOnce() -> Fut,
+) -> Result<T, E>
+where
+  T: Serialize + DeserializeOwned,
+  Fut: Future<Output = Result<T, E>>,
+{
+  match get(redis_conn, key).await {
+    Ok(Some(value)) => {
+      metrics::increment_counter!("cache_lookups_total", "outcome" => "hit");
+      return Ok(value);
+    }
+    Ok(None) => metrics::increment_counter!("cache_lookups_total", "outcome" => "miss"),
+    Err(err) => {
+      metrics::increment_counter!("cache_lookups_total", "outcome" => "error");
+      tracing::warn!(key, error = %err, "reading cache failed");
+      return load().await;
+    }
+  }
+
+  let lease = match Lease::acquire(redis_conn, &entry_key(key), LOAD_LOCK_TTL).await {
+    Ok(Some(lease)) => Some(lease),
+    Ok(None) => {
+      if let Some(value) = wait_for(redis_conn, key).await {
+        return Ok(value);
+      }
+      None
+    }
+    Err(err) => {
+      tracing::warn!(key, error = %err, "locking cache entry failed");
+      None
+    }
+  };
+
+  let tag_generations = generations(redis_conn, tags).await;
+  let loaded = load().await;
+
+  match (&loaded, tag_generations) {
+    (Ok(value), Ok(generations)) => {
+      match set(redis_conn, key, value, ttl(value), tags, &generations).await {
+        Ok(true) => {}
+        Ok(false) => tracing::debug!(key, "cache was invalidated while loading, not storing"),
+        Err(err) => tracing::warn!(key, error = %err, "writing cache failed"),
+      }
+    }
+    (Ok(_), Err(err)) => tracing::warn!(key, error = %err, "reading cache generations failed"),
+    (Err(_), _) => {}
+  }
+
+  if let Some(lease) = lease {
+    if let Err(err) = lease.release().await {
+      tracing::warn!(key, error = %err, "unlocking cache entry failed");
+    }
+  }
+
+  loaded
+}
+
+// Polls for the value another caller is loading, giving up once its lock would have expired.
+async fn wait_for<T: DeserializeOwned>(redis_conn: &mut RedisConn, key: &str) -> Option<T> {
+  let deadline = Instant::now() + LOAD_LOCK_TTL;
+
+  while Instant::now() < deadline {
+    tokio::time::sleep(LOAD_POLL_INTERVAL).await;
+
+    match get(redis_conn, key).await {
+      Ok(Some(value)) => return Some(value),
+      Ok(None) => {}
+      Err(_) => return None,
+    }
+  }
+
+  None
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::utils::random_string;
+  use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+  };
+
+  // A server that answers every command with an error, as a Redis that is out of memory or read only.
+  async fn failing_redis() -> RedisConn {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+      while let Ok((mut socket, _)) = listener.accept().await {
+        tokio::spawn(async move {
+          let mut buf = [0; 4096];
+          while matches!(socket.read(&mut buf).await, Ok(read) if read > 0) {
+            if socket.write_all(b"-ERR unavailable\r\n").await.is_err() {
+              break;
+            }
+          }
+        });
+      }
+    });
+
+    RedisConn::connect(&format!("redis://{addr}/"))
+      .await
+      .unwrap()
+  }
+
+  // The scripts need a real Redis, tests using one are skipped unless `TEST_REDIS_URL` is set.
+  async fn test_redis() -> Option<RedisConn> {
+    match std::env::var("TEST_REDIS_URL") {
+      Ok(url) => Some(RedisConn::connect(&url).await.unwrap()),
+      Err(_) => None,
+    }
+  }
+
+  async fn listing(redis_conn: &mut RedisConn, key: &str, tag: &str, loaded: Vec<i32>) -> Vec<i32> {
+    get_or_load(
+      redis_conn,
+      key,
+      &[tag],
+      |_| Duration::from_secs(60),
+      move || async move { Ok::<_, ()>(loaded) },
+    )
+    .await
+    .unwrap()
+  }
+
+  #[test]
+  fn keys_and_tags_share_the_cache_namespace() {
+    assert_eq!(entry_key("banners:active"), "cache:banners:active");
+    assert_eq!(tag_key("banners"), "cache:tag:banners");
+    assert_eq!(generation_key("banners"), "cache:tag:banners:generation");
+  }
+
+  #[test]
+  fn hashed_keys_are_bounded_and_unambiguous() {
+    let key = hashed_key("businesses:sample", &(Some("a:b"), None::<&str>, false));
+
+    assert!(key.starts_with("businesses:sample:"));
+    assert_eq!(key.len(), "businesses:sample:".len() + 64);
+    assert_eq!(
+      key,
+      hashed_key("businesses:sample", &(Some("a:b"), None::<&str>, false))
+    );
+    assert_ne!(
+      key,
+      hashed_key("businesses:sample", &(Some("a"), Some("b"), false))
+    );
+    assert_ne!(
+      hashed_key("businesses:sample", &(Some("*"), None::<&str>, false)),
+      hashed_key("businesses:sample", &(None::<&str>, None::<&str>, false))
+    );
+  }
+
+  #[tokio::test]
+  async fn loads_directly_when_redis_fails() {
+    let mut redis_conn = failing_redis().await;
+
+    let loaded: Result<Vec<i32>, ()> = get_or_load(
+      &mut redis_conn,
+      "numbers",
+      &["numbers"],
+      |_| Duration::from_secs(60),
+      || async { Ok(vec![1, 2, 3]) },
+    )
+    .await;
+
+    assert_eq!(loaded, Ok(vec![1, 2, 3]));
+  }
+
+  #[tokio::test]
+  async fn load_errors_are_returned_when_redis_fails() {
+    let mut redis_conn = failing_redis().await;
+
+    let loaded: Result<Vec<i32>, &str> = get_or_load(
+      &mut redis_conn,
+      "numbers",
+      &["numbers"],
+      |_| Duration::from_secs(60),
+      || async { Err("database is down") },
+    )
+    .await;
+
+    assert_eq!(loaded, Err("database is down"));
+  }
+
+  #[tokio::test]
+  async fn invalidated_listings_are_loaded_again() {
+    let mut redis_conn = match test_redis().await {
+      Some(redis_conn) => redis_conn,
+      None => return,
+    };
+    let tag = format!("test:{}", random_string(16));
+    let key = format!("{tag}:listing");
+
+    assert_eq!(listing(&mut redis_conn, &key, &tag, vec![1]).await, vec![1]);
+    assert_eq!(listing(&mut redis_conn, &key, &tag, vec![2]).await, vec![1]);
+
+    invalidate_tag(&mut redis_conn, &tag).await;
+
+    assert_eq!(listing(&mut redis_conn, &key, &tag, vec![3]).await, vec![3]);
+  }
+}
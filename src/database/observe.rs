@@ -0,0 +1,28 @@
+use futures::future::BoxFuture;
+use prisma_client_rust::QueryError;
+use std::{future::Future, time::Instant};
+use tracing::Instrument;
+
+// Traces a Prisma query and records how long it took, labelled by `query` such as `user.find_unique`.
+pub trait ObserveQuery<'a, T>: Future<Output = Result<T, QueryError>> + Send + Sized + 'a {
+  fn observe(self, query: &'static str) -> BoxFuture<'a, Result<T, QueryError>> {
+    Box::pin(
+      async move {
+        let started = Instant::now();
+        let result = self.await;
+
+        metrics::histogram!(
+          "prisma_query_duration_seconds",
+          started.elapsed().as_secs_f64(),
+          "query" => query,
+          "outcome" => if result.is_ok() { "ok" } else { "error" },
+        );
+
+        result
+      }
+      .instrument(tracing::info_span!("db", query)),
+    )
+  }
+}
+
+impl<'a, T, F> ObserveQuery<'a, T> for F where F: Future<Output = Result<T, QueryError>> + Send + 'a {}
@@ -0,0 +1,82 @@
+use redis::{
+  aio::{ConnectionLike, ConnectionManager},
+  Arg, Cmd, Pipeline, RedisFuture, RedisResult, Value,
+};
+use std::time::Instant;
+use tracing::Instrument;
+
+// A `ConnectionManager` that traces every round trip to Redis and reports its latency and failures.
+#[derive(Clone)]
+pub struct RedisConn(ConnectionManager);
+
+impl RedisConn {
+  pub async fn connect(url: &str) -> RedisResult<Self> {
+    Ok(Self(
+      ConnectionManager::new(redis::Client::open(url)?).await?,
+    ))
+  }
+
+  // Ends the connection shared by every clone, a clone that is still used reconnects on its next command.
+  pub async fn close(mut self) -> RedisResult<()> {
+    redis::cmd("QUIT").query_async(&mut self).await
+  }
+}
+
+// Only the name of the command is traced, its arguments may hold tokens or user data.
+fn command_name(cmd: &Cmd) -> String {
+  match cmd.args_iter().next() {
+    Some(Arg::Simple(name)) => String::from_utf8_lossy(name).to_uppercase(),
+    _ => "UNKNOWN".to_string(),
+  }
+}
+
+fn observe<T>(started: Instant, result: RedisResult<T>) -> RedisResult<T> {
+  metrics::histogram!(
+    "redis_command_duration_seconds",
+    started.elapsed().as_secs_f64()
+  );
+
+  if let Err(err) = &result {
+    metrics::increment_counter!("redis_errors_total", "kind" => format!("{:?}", err.kind()));
+  }
+
+  result
+}
+
+impl ConnectionLike for RedisConn {
+  fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+    let span = tracing::info_span!("redis", command = %command_name(cmd));
+
+    Box::pin(
+      async move {
+        let started = Instant::now();
+        observe(started, self.0.req_packed_command(cmd).await)
+      }
+      .instrument(span),
+    )
+  }
+
+  fn req_packed_commands<'a>(
+    &'a mut self,
+    cmd: &'a Pipeline,
+    offset: usize,
+    count: usize,
+  ) -> RedisFuture<'a, Vec<Value>> {
+    let span = tracing::info_span!("redis", command = "PIPELINE", count);
+
+    Box::pin(
+      async move {
+        let started = Instant::now();
+        observe(
+          started,
+          self.0.req_packed_commands(cmd, offset, count).await,
+        )
+      }
+      .instrument(span),
+    )
+  }
+
+  fn get_db(&self) -> i64 {
+    self.0.get_db()
+  }
+}
@@ -1,3 +1,6 @@
+pub mod cors;
 pub mod did;
+pub mod rate_limit;
+pub mod request_id;
 pub mod sercurity;
 pub mod validate;
@@ -0,0 +1,122 @@
+use super::request_id::REQUEST_ID_HEADER;
+use crate::config::CorsPolicy;
+use anyhow::{bail, Context, Result};
+use axum::http::{header, HeaderName, HeaderValue, Method};
+use std::str::FromStr;
+use tower_http::cors::{AllowOrigin, CorsLayer, ExposeHeaders};
+
+// An allowed origin, either exact or `https://*.example.com` for every subdomain of `example.com`.
+// The wildcard never matches the apex domain itself.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OriginPattern {
+  Exact(HeaderValue),
+  Subdomain { scheme: String, suffix: String },
+}
+
+impl FromStr for OriginPattern {
+  type Err = anyhow::Error;
+
+  fn from_str(value: &str) -> Result<Self> {
+    let value = value.to_lowercase();
+    let (scheme, host) = value
+      .split_once("://")
+      .with_context(|| format!("`{value}` is not an origin, expected <scheme>://<host>"))?;
+
+    if host.is_empty() || host.contains('/') {
+      bail!("`{value}` is not an origin, it must not have a path");
+    }
+
+    match host.strip_prefix("*.") {
+      Some(suffix) if !suffix.is_empty() && !suffix.contains('*') => Ok(Self::Subdomain {
+        scheme: scheme.to_string(),
+        suffix: format!(".{suffix}"),
+      }),
+      _ if host.contains('*') => bail!("`{value}` may only start its host with `*.`"),
+      _ => Ok(Self::Exact(HeaderValue::from_str(&value)?)),
+    }
+  }
+}
+
+impl OriginPattern {
+  pub fn matches(&self, origin: &HeaderValue) -> bool {
+    match self {
+      Self::Exact(allowed) => allowed == origin,
+      Self::Subdomain { scheme, suffix } => origin
+        .to_str()
+        .ok()
+        .and_then(|origin| origin.strip_prefix(scheme.as_str()))
+        .and_then(|origin| origin.strip_prefix("://"))
+        .and_then(|host| host.strip_suffix(suffix.as_str()))
+        .map(|subdomain| {
+          !subdomain.is_empty()
+            && subdomain
+              .chars()
+              .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        })
+        .unwrap_or(false),
+    }
+  }
+}
+
+// Methods and request headers are listed explicitly, browsers refuse wildcards on credentialed requests.
+pub fn layer(policy: &CorsPolicy) -> CorsLayer {
+  let origins = policy.origins.to_owned();
+
+  CorsLayer::new()
+    .allow_origin(if origins.is_empty() {
+      AllowOrigin::any()
+    } else {
+      AllowOrigin::predicate(move |origin, _| origins.iter().any(|allowed| allowed.matches(origin)))
+    })
+    .allow_methods([
+      Method::GET,
+      Method::POST,
+      Method::PUT,
+      Method::PATCH,
+      Method::DELETE,
+    ])
+    .allow_headers([
+      header::AUTHORIZATION,
+      header::CONTENT_TYPE,
+      HeaderName::from_static(REQUEST_ID_HEADER),
+    ])
+    .allow_credentials(policy.allow_credentials)
+    .expose_headers(ExposeHeaders::list(policy.expose_headers.to_owned()))
+    .max_age(policy.max_age)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn origin(value: &'static str) -> HeaderValue {
+    HeaderValue::from_static(value)
+  }
+
+  #[test]
+  fn wildcard_matches_subdomains_only() {
+    let pattern: OriginPattern = "https://*.example.com".parse().unwrap();
+
+    assert!(pattern.matches(&origin("https://app.example.com")));
+    assert!(pattern.matches(&origin("https://eu.app.example.com")));
+    assert!(!pattern.matches(&origin("https://example.com")));
+    assert!(!pattern.matches(&origin("http://app.example.com")));
+    assert!(!pattern.matches(&origin("https://app.example.com.evil.io")));
+    assert!(!pattern.matches(&origin("https://evil.io/.example.com")));
+  }
+
+  #[test]
+  fn rejects_malformed_patterns() {
+    for pattern in [
+      "example.com",
+      "https://example.com/",
+      "https://app.*.example.com",
+      "https://*.",
+    ] {
+      assert!(
+        pattern.parse::<OriginPattern>().is_err(),
+        "{pattern} was accepted"
+      );
+    }
+  }
+}
@@ -1,14 +1,79 @@
-use super::sercurity::{decode_jwt, Claims};
-use crate::AppState;
+use super::sercurity::{bearer_token, decode_jwt, Claims};
+use crate::database::observe::ObserveQuery;
+use crate::database::redis_conn::RedisConn;
+use crate::{database::prisma::PrismaClient, AppState};
 use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
-use std::env;
+use prisma_client_rust::QueryError;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug)]
+const DID_CACHE_TTL_SECONDS: usize = 10 * 60;
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Did {
   pub controller_address: String,
   pub ids: Vec<i32>,
 }
 
+fn did_cache_key(user_id: i32) -> String {
+  format!("did:{user_id}")
+}
+
+// Must be called with every user id of a DID whenever its controller or linked wallets change.
+#[tracing::instrument(skip_all, fields(?ids))]
+pub async fn invalidate_did_cache(redis_conn: &mut RedisConn, ids: &[i32]) {
+  if ids.is_empty() {
+    return;
+  }
+
+  let keys: Vec<String> = ids.iter().map(|id| did_cache_key(*id)).collect();
+
+  if let Err(err) = redis::cmd("DEL")
+    .arg(keys)
+    .query_async::<_, ()>(redis_conn)
+    .await
+  {
+    tracing::warn!(?ids, error = %err, "invalidating cached did failed");
+  }
+}
+
+#[tracing::instrument(skip_all, fields(user_id = claims.id))]
+async fn resolve_did(prisma_client: &PrismaClient, claims: &Claims) -> Result<Did, QueryError> {
+  use crate::database::prisma::{did, user};
+
+  let (users, did) = tokio::join!(
+    prisma_client
+      .user()
+      .find_many(vec![user::did::is(vec![did::users::some(vec![
+        user::id::equals(claims.id),
+      ])])])
+      .select(user::select!({ id }))
+      .exec()
+      .observe("user.find_many"),
+    prisma_client
+      .did()
+      .find_first(vec![did::users::some(vec![user::id::equals(claims.id)])])
+      .select(did::select!({ controller }))
+      .exec()
+      .observe("did.find_first")
+  );
+
+  let users = users?;
+  let did = did?;
+
+  Ok(Did {
+    controller_address: if let Some(did) = did {
+      did.controller
+    } else {
+      claims.wallet_address.to_owned()
+    },
+    ids: if users.len() > 0 {
+      users.iter().map(|u| u.id).collect()
+    } else {
+      vec![claims.id]
+    },
+  })
+}
+
 #[async_trait]
 impl FromRequestParts<AppState> for Option<Did> {
   type Rejection = ();
@@ -22,52 +87,54 @@ impl FromRequestParts<AppState> for Option<Did> {
         if authoration_header.is_empty() {
           Ok(None)
         } else {
-          dbg!("case else");
-          let token = authoration_header
-            .to_str()
-            .unwrap()
-            .trim_start_matches("Bearer")
-            .trim();
+          let token = match bearer_token(authoration_header) {
+            Some(token) => token,
+            None => return Ok(None),
+          };
 
-          let access_secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set.");
+          match decode_jwt::<Claims>(token, state.config.auth.jwt_secret.to_owned()) {
+            Ok(claims) => {
+              let mut redis_conn = state.redis_conn.clone();
+              let cache_key = did_cache_key(claims.id);
 
-          dbg!(token);
+              match redis::cmd("GET")
+                .arg(&cache_key)
+                .query_async::<_, Option<String>>(&mut redis_conn)
+                .await
+              {
+                Ok(Some(cached)) => {
+                  if let Ok(did) = serde_json::from_str::<Did>(&cached) {
+                    return Ok(Some(did));
+                  }
+                }
+                Ok(None) => {}
+                Err(err) => {
+                  tracing::warn!(user_id = claims.id, error = %err, "reading cached did failed")
+                }
+              }
 
-          match decode_jwt::<Claims>(token, access_secret) {
-            Ok(claims) => {
-              let prisma_client = &state.prisma_client;
-              use crate::database::prisma::{did, user};
-
-              let (users, did) = tokio::join!(
-                prisma_client
-                  .user()
-                  .find_many(vec![user::did::is(vec![did::users::some(vec![
-                    user::id::equals(claims.id),
-                  ])])])
-                  .select(user::select!({ id }))
-                  .exec(),
-                prisma_client
-                  .did()
-                  .find_first(vec![did::users::some(vec![user::id::equals(claims.id)])])
-                  .select(did::select!({ controller }))
-                  .exec()
-              );
-
-              let users = users.unwrap();
-              let did = did.unwrap();
-
-              Ok(Some(Did {
-                controller_address: if let Some(did) = did {
-                  did.controller
-                } else {
-                  claims.wallet_address
-                },
-                ids: if users.len() > 0 {
-                  users.iter().map(|u| u.id).collect()
-                } else {
-                  vec![claims.id]
-                },
-              }))
+              let did = match resolve_did(&state.prisma_client, &claims).await {
+                Ok(did) => did,
+                Err(err) => {
+                  tracing::warn!(user_id = claims.id, error = %err, "resolving did failed");
+                  return Ok(None);
+                }
+              };
+
+              if let Ok(serialized) = serde_json::to_string(&did) {
+                if let Err(err) = redis::cmd("SET")
+                  .arg(&cache_key)
+                  .arg(serialized)
+                  .arg("EX")
+                  .arg(DID_CACHE_TTL_SECONDS)
+                  .query_async::<_, ()>(&mut redis_conn)
+                  .await
+                {
+                  tracing::warn!(user_id = claims.id, error = %err, "caching did failed");
+                }
+              }
+
+              Ok(Some(did))
             }
             Err(_) => Ok(None),
           }
@@ -0,0 +1,163 @@
+use super::sercurity::{bearer_token, decode_jwt, Claims};
+use crate::config::{RateLimitConfig, RateLimitPolicy};
+use crate::routes::RouteGroup;
+use crate::utils::random_string;
+use crate::AppState;
+use axum::{
+  extract::{ConnectInfo, State},
+  http::{header, HeaderMap, Request},
+  middleware::Next,
+  response::{IntoResponse, Response},
+};
+use error::AppError;
+use std::net::SocketAddr;
+
+// Sliding window over a sorted set of request timestamps, the clock of Redis is used so that every
+// instance agrees on it. Returns 0 when the request is allowed, otherwise the milliseconds until the
+// oldest request leaves the window. Rejected requests are not recorded.
+const SLIDING_WINDOW_SCRIPT: &str = r#"
+  local time = redis.call("TIME")
+  local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+  local window = tonumber(ARGV[1])
+
+  redis.call("ZREMRANGEBYSCORE", KEYS[1], 0, now - window)
+
+  if redis.call("ZCARD", KEYS[1]) < tonumber(ARGV[2]) then
+    redis.call("ZADD", KEYS[1], now, ARGV[3])
+    redis.call("PEXPIRE", KEYS[1], window)
+    return 0
+  end
+
+  local oldest = redis.call("ZRANGE", KEYS[1], 0, 0, "WITHSCORES")
+  return math.max(tonumber(oldest[2]) + window - now, 1)
+"#;
+
+pub fn policy(config: &RateLimitConfig, group: RouteGroup) -> Option<RateLimitPolicy> {
+  match group {
+    RouteGroup::Probe => None,
+    RouteGroup::Auth => config.auth,
+    RouteGroup::Public => config.public,
+    RouteGroup::User => config.user,
+    RouteGroup::Admin => config.admin,
+  }
+}
+
+#[derive(Clone)]
+pub struct RateLimit {
+  group: RouteGroup,
+  policy: RateLimitPolicy,
+  state: AppState,
+}
+
+impl RateLimit {
+  pub fn new(group: RouteGroup, policy: RateLimitPolicy, state: &AppState) -> Self {
+    Self {
+      group,
+      policy,
+      state: state.clone(),
+    }
+  }
+}
+
+// The load balancer appends the address it received the request from, earlier entries are set by the client.
+fn client_ip(headers: &HeaderMap, peer: Option<SocketAddr>, trust_forwarded_for: bool) -> String {
+  let forwarded_for = headers
+    .get("x-forwarded-for")
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.rsplit(',').next())
+    .map(str::trim)
+    .filter(|ip| !ip.is_empty());
+
+  match (trust_forwarded_for, forwarded_for, peer) {
+    (true, Some(ip), _) => ip.to_string(),
+    (_, _, Some(peer)) => peer.ip().to_string(),
+    _ => "unknown".to_string(),
+  }
+}
+
+// Guarded routes are limited per user, so that users behind the same NAT do not share a budget.
+// Requests without a valid token are limited per IP, the guard rejects them anyway.
+fn subject<B>(limit: &RateLimit, request: &Request<B>) -> String {
+  let config = &limit.state.config;
+
+  let user_id = request
+    .headers()
+    .get(header::AUTHORIZATION)
+    .filter(|_| limit.group.is_guarded())
+    .and_then(bearer_token)
+    .and_then(|token| decode_jwt::<Claims>(token, config.auth.jwt_secret.to_owned()).ok())
+    .map(|claims| claims.id);
+
+  match user_id {
+    Some(user_id) => format!("user:{user_id}"),
+    None => {
+      let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(peer)| *peer);
+      let ip = client_ip(
+        request.headers(),
+        peer,
+        config.rate_limit.trust_forwarded_for,
+      );
+
+      format!("ip:{ip}")
+    }
+  }
+}
+
+// Fails open, an unreachable Redis must not lock everybody out.
+pub async fn rate_limit<B>(
+  State(limit): State<RateLimit>,
+  request: Request<B>,
+  next: Next<B>,
+) -> Response {
+  let key = format!(
+    "rate_limit:{}:{}",
+    limit.group.name(),
+    subject(&limit, &request)
+  );
+  let mut redis_conn = limit.state.redis_conn.clone();
+
+  let retry_after_ms = redis::Script::new(SLIDING_WINDOW_SCRIPT)
+    .key(&key)
+    .arg(limit.policy.window.as_millis() as u64)
+    .arg(limit.policy.limit)
+    .arg(random_string(16))
+    .invoke_async::<_, u64>(&mut redis_conn)
+    .await;
+
+  match retry_after_ms {
+    Ok(0) => next.run(request).await,
+    Ok(retry_after_ms) => {
+      metrics::increment_counter!("rate_limited_total", "group" => limit.group.name());
+
+      AppError::too_many_requests((retry_after_ms + 999) / 1000).into_response()
+    }
+    Err(err) => {
+      tracing::warn!(key, error = %err, "rate limiting failed, letting the request through");
+      next.run(request).await
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use axum::http::HeaderValue;
+
+  #[test]
+  fn forwarded_for_is_only_used_when_trusted() {
+    let peer = Some(SocketAddr::from(([10, 0, 0, 1], 443)));
+    let mut headers = HeaderMap::new();
+    headers.insert(
+      "x-forwarded-for",
+      HeaderValue::from_static("1.1.1.1, 203.0.113.7"),
+    );
+
+    assert_eq!(client_ip(&headers, peer, true), "203.0.113.7");
+    assert_eq!(client_ip(&headers, peer, false), "10.0.0.1");
+    assert_eq!(client_ip(&HeaderMap::new(), peer, true), "10.0.0.1");
+    assert_eq!(client_ip(&HeaderMap::new(), None, false), "unknown");
+  }
+}
@@ -0,0 +1,32 @@
+use crate::utils::random_string;
+use axum::{
+  http::{HeaderValue, Request},
+  middleware::Next,
+  response::Response,
+};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Reuses the id set by a proxy in front of us, otherwise mints one, and echoes it back on the response.
+// The id is also written to the request so that the trace layer behind this one can record it.
+pub async fn request_id<B>(mut req: Request<B>, next: Next<B>) -> Response {
+  let request_id = req
+    .headers()
+    .get(REQUEST_ID_HEADER)
+    .and_then(|value| value.to_str().ok())
+    .filter(|value| !value.is_empty() && value.len() <= 128)
+    .map(str::to_owned)
+    .unwrap_or_else(|| random_string(24));
+
+  if let Ok(value) = HeaderValue::from_str(&request_id) {
+    req.headers_mut().insert(REQUEST_ID_HEADER, value);
+  }
+
+  let mut response = error::with_request_id(request_id.to_owned(), next.run(req)).await;
+
+  if let Ok(value) = HeaderValue::from_str(&request_id) {
+    response.headers_mut().insert(REQUEST_ID_HEADER, value);
+  }
+
+  response
+}
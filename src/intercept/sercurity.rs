@@ -1,10 +1,13 @@
-use crate::services::auth::user_claims;
-use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use crate::{services::auth::user_claims, AppState};
+use axum::{
+  async_trait,
+  extract::FromRequestParts,
+  http::{request::Parts, HeaderValue},
+};
 use chrono::Utc;
 use error::AuthError;
 use jsonwebtoken::{decode, errors::ErrorKind, Algorithm, DecodingKey, Validation};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use std::env;
 
 #[derive(Serialize, Deserialize)]
 pub struct Claims {
@@ -14,25 +17,20 @@ pub struct Claims {
   pub is_admin: bool,
 }
 pub struct Guard(pub Claims);
+pub struct AdminGuard(pub Claims);
 
 impl Claims {
-  pub fn new_access(user_claims: &user_claims::Data) -> Self {
+  pub fn new_access(user_claims: &user_claims::Data, ttl: chrono::Duration) -> Self {
     Self {
-      exp: Utc::now()
-        .checked_add_signed(chrono::Duration::days(3))
-        .unwrap()
-        .timestamp() as u32,
+      exp: Utc::now().checked_add_signed(ttl).unwrap().timestamp() as u32,
       id: user_claims.id,
       wallet_address: user_claims.wallet_address.to_owned(),
       is_admin: user_claims.is_admin,
     }
   }
-  pub fn new_refresh(user_claims: &user_claims::Data) -> Self {
+  pub fn new_refresh(user_claims: &user_claims::Data, ttl: chrono::Duration) -> Self {
     Self {
-      exp: Utc::now()
-        .checked_add_signed(chrono::Duration::days(60))
-        .unwrap()
-        .timestamp() as u32,
+      exp: Utc::now().checked_add_signed(ttl).unwrap().timestamp() as u32,
       id: user_claims.id,
       wallet_address: user_claims.wallet_address.to_owned(),
       is_admin: user_claims.is_admin,
@@ -41,28 +39,28 @@ impl Claims {
 }
 
 #[async_trait]
-impl<S> FromRequestParts<S> for Guard
-where
-  S: Send + Sync,
-{
+impl FromRequestParts<AppState> for Guard {
   type Rejection = AuthError;
 
-  async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+  async fn from_request_parts(
+    parts: &mut Parts,
+    state: &AppState,
+  ) -> Result<Self, Self::Rejection> {
     match parts.headers.get("Authorization") {
       Some(authoration_header) => {
         if authoration_header.is_empty() {
           Err(AuthError::MissingCredentials)
         } else {
-          let token = authoration_header
-            .to_str()
-            .unwrap()
-            .trim_start_matches("Bearer")
-            .trim();
+          let token = match bearer_token(authoration_header) {
+            Some(token) => token,
+            None => return Err(AuthError::WrongCredentials),
+          };
 
-          let access_secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set.");
-
-          match decode_jwt::<Claims>(token, access_secret) {
-            Ok(claims) => Ok(Guard(claims)),
+          match decode_jwt::<Claims>(token, state.config.auth.jwt_secret.to_owned()) {
+            Ok(claims) => {
+              metrics::increment_counter!("auth_outcomes_total", "outcome" => "authenticated");
+              Ok(Guard(claims))
+            }
             Err(err) => {
               if let ErrorKind::ExpiredSignature = err.kind() {
                 Err(AuthError::ExpriedCredentials)
@@ -78,6 +76,30 @@ where
   }
 }
 
+#[async_trait]
+impl FromRequestParts<AppState> for AdminGuard {
+  type Rejection = AuthError;
+
+  async fn from_request_parts(
+    parts: &mut Parts,
+    state: &AppState,
+  ) -> Result<Self, Self::Rejection> {
+    let Guard(claims) = Guard::from_request_parts(parts, state).await?;
+
+    if claims.is_admin {
+      Ok(AdminGuard(claims))
+    } else {
+      Err(AuthError::PermissionDenied)
+    }
+  }
+}
+
+// The token of an `Authorization: Bearer <token>` header, None for any other scheme or a value that is
+// not visible ASCII.
+pub fn bearer_token(header: &HeaderValue) -> Option<&str> {
+  header.to_str().ok()?.strip_prefix("Bearer ").map(str::trim)
+}
+
 pub fn decode_jwt<T: DeserializeOwned>(
   token: &str,
   secret: String,
@@ -91,3 +113,23 @@ pub fn decode_jwt<T: DeserializeOwned>(
     Err(err) => Err(err),
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn only_bearer_tokens_are_extracted() {
+    let token =
+      |value: &[u8]| bearer_token(&HeaderValue::from_bytes(value).unwrap()).map(String::from);
+
+    assert_eq!(
+      token(b"Bearer abc.def.ghi"),
+      Some("abc.def.ghi".to_string())
+    );
+    assert_eq!(token(b"Bearerabc.def.ghi"), None);
+    assert_eq!(token(b"Basic dXNlcjpwYXNz"), None);
+    // Not visible ASCII, `to_str` fails instead of panicking the extractor.
+    assert_eq!(token(b"Bearer \xff\xfe"), None);
+  }
+}
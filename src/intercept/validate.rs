@@ -25,10 +25,10 @@ where
     if let Ok(Query(data)) = query {
       match data.validate() {
         Ok(_) => Ok(ValidatedQuery(data)),
-        Err(err) => Err(ValidateError::from(err.to_string())),
+        Err(err) => Err(ValidateError::from(err)),
       }
     } else {
-      Err(ValidateError::from("Invalid query string"))
+      Err(ValidateError::InvalidQuery)
     }
   }
 }
@@ -49,9 +49,9 @@ where
     match json {
       Ok(Json(json_body)) => match json_body.validate() {
         Ok(_) => Ok(ValidatedJson(json_body)),
-        Err(err) => Err(ValidateError::from(err.to_string())),
+        Err(err) => Err(ValidateError::from(err)),
       },
-      Err(_) => Err(ValidateError::from("Invalid json body")),
+      Err(_) => Err(ValidateError::InvalidJson),
     }
   }
 }
@@ -0,0 +1,29 @@
+#![recursion_limit = "256"]
+pub mod config;
+pub mod database;
+pub mod intercept;
+pub mod market_data;
+pub mod open_api;
+pub mod routes;
+pub mod schedulers;
+pub mod services;
+pub mod shutdown;
+pub mod storage;
+pub mod telemetry;
+pub mod utils;
+use config::Config;
+use database::{prisma::PrismaClient, redis_conn::RedisConn};
+use metrics_exporter_prometheus::PrometheusHandle;
+use schedulers::registry::JobRegistry;
+use std::sync::Arc;
+use storage::FileStorage;
+
+#[derive(Clone)]
+pub struct AppState {
+  pub config: Arc<Config>,
+  pub prisma_client: Arc<PrismaClient>,
+  pub redis_conn: RedisConn,
+  pub storage: Arc<dyn FileStorage>,
+  pub jobs: Arc<JobRegistry>,
+  pub metrics: PrometheusHandle,
+}
@@ -1,76 +1,153 @@
-#![recursion_limit = "256"]
-mod database;
-mod intercept;
-mod open_api;
-mod schedulers;
-mod services;
-mod utils;
-use axum::{
-  routing::{get, post},
-  Router,
+use axum::{http::header, middleware, Router};
+use axum_baby::{
+  config::Config,
+  database::{prisma::PrismaClient, redis_conn::RedisConn},
+  intercept, market_data,
+  open_api::ApiDoc,
+  routes,
+  schedulers::{
+    banner::BannerArchive, cmc::CmcCrawl, registry::JobRegistry, snapshot::SnapshotRollup,
+  },
+  shutdown, storage, telemetry, AppState,
 };
-use database::prisma::PrismaClient;
-use dotenv::dotenv;
 // use futures::prelude::*;
-use open_api::ApiDoc;
-use schedulers::cmc::CmcCrawling;
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
+use tokio::sync::oneshot;
 use tokio_cron_scheduler::JobScheduler;
-use tower_http::cors::{Any, CorsLayer};
+use tower_http::sensitive_headers::SetSensitiveRequestHeadersLayer;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
-#[derive(Clone)]
-pub struct AppState {
-  prisma_client: Arc<PrismaClient>,
-  redis_conn: redis::aio::ConnectionManager,
-}
-
 #[tokio::main]
 async fn main() {
-  dotenv().ok();
+  let config = Arc::new(Config::load().expect("loading config was wrong"));
+  telemetry::init(&config);
+  let metrics =
+    telemetry::install_metrics_recorder().expect("installing metrics recorder was wrong");
+
+  error::hide_internal_errors(!config.expose_internal_errors);
 
   let prisma_client = Arc::new(
     PrismaClient::_builder()
+      .with_url(config.database_url.to_owned())
       .build()
       .await
       .expect("creating prisma was wrong"),
   );
 
-  let redis_conn = redis::aio::ConnectionManager::new(
-    redis::Client::open("redis://127.0.0.1/").expect("opening redis client fail"),
-  )
-  .await
-  .unwrap();
+  let redis_conn = RedisConn::connect(&config.redis_url)
+    .await
+    .expect("connecting to redis was wrong");
+
+  let storage = storage::from_config(&config.storage).expect("creating storage was wrong");
+
+  let market_data = market_data::from_config(&config.market_data, &redis_conn)
+    .expect("creating market data provider was wrong");
+
+  let jobs = Arc::new(
+    JobRegistry::new(redis_conn.clone(), config.job_schedules.to_owned())
+      .register(CmcCrawl::new(prisma_client.clone(), redis_conn.clone(), market_data))
+      .register(SnapshotRollup::new(prisma_client.clone()))
+      .register(BannerArchive::new(prisma_client.clone(), redis_conn.clone()))
+      .finish()
+      .expect("registering jobs was wrong"),
+  );
 
   let app_state = AppState {
-    prisma_client,
-    redis_conn,
+    config: config.clone(),
+    prisma_client: prisma_client.clone(),
+    redis_conn: redis_conn.clone(),
+    storage,
+    jobs: jobs.clone(),
+    metrics,
   };
 
-  let app = Router::new()
+  let process_mode = config.process_mode;
+  let mut sched = JobScheduler::new().await.unwrap();
+
+  if process_mode.runs_scheduler() {
+    app_state.jobs.schedule(&sched).await.unwrap();
+    sched.start().await.unwrap();
+  }
+
+  let app = if process_mode.runs_api() {
+    api_app(&app_state)
+  } else {
+    // A scheduler-only process still answers probes and serves the crawl counters on its metrics.
+    Router::new()
+  }
+  .merge(routes::probe_routes().into_router(&app_state))
+  // Outside the CORS policies of the route groups, metrics are scraped server side.
+  .merge(telemetry::metrics_router())
+  // Outermost, so that nothing behind it can print a bearer token.
+  .layer(SetSensitiveRequestHeadersLayer::new([header::AUTHORIZATION]))
+  .with_state(app_state);
+
+  serve(&config, app).await;
+
+  // No new run starts from here, the running ones get what is left of the shutdown timeout.
+  if process_mode.runs_scheduler() {
+    if let Err(err) = sched.shutdown().await {
+      tracing::error!(error = %err, "stopping the scheduler failed");
+    }
+  }
+  drop(sched);
+
+  let drain_timeout = shutdown::remaining(config.shutdown_timeout);
+  if !jobs.drain(drain_timeout).await {
+    tracing::warn!(timeout = ?drain_timeout, "jobs were still running, abandoning them");
+  }
+  drop(jobs);
+
+  if let Err(err) = redis_conn.close().await {
+    tracing::warn!(error = %err, "closing the redis connection failed");
+  }
+
+  // The pool closes with the last handle, abandoned jobs still hold theirs until the process exits.
+  match Arc::try_unwrap(prisma_client) {
+    Ok(prisma_client) => drop(prisma_client),
+    Err(_) => tracing::warn!("the database pool is still in use, leaving it to the exit"),
+  }
+
+  tracing::info!("shutdown complete");
+}
+
+fn api_app(app_state: &AppState) -> Router<AppState> {
+  routes::api_routes()
+    .into_router(app_state)
+    .route_layer(middleware::from_fn(telemetry::track_http))
+    .merge(storage::router(&app_state.config.storage))
     .merge(SwaggerUi::new("/docs").url("/api-docs/openapi.json", ApiDoc::openapi()))
-    .route("/auth/nonce", get(services::auth::get_nonce))
-    .route("/auth/login", post(services::auth::login))
-    .route("/users", get(services::user::who_am_i))
-    .route("/businesses", get(services::business::get_businesses))
-    .layer(
-      CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any),
-    )
-    .with_state(app_state);
-
-  let sched = JobScheduler::new().await.unwrap();
-  sched.crawl_cmc().await.unwrap();
-  sched.start().await.unwrap();
-
-  // run it with hyper on localhost:8080
-  axum::Server::bind(&"0.0.0.0:8080".parse().unwrap())
-    .serve(app.into_make_service())
-    .await
-    .unwrap();
+    .layer(telemetry::trace_layer())
+    .layer(middleware::from_fn(intercept::request_id::request_id))
+}
+
+// Serves until a shutdown signal, then stops accepting connections and waits for in-flight requests,
+// at most `shutdown_timeout` since the signal before dropping them.
+async fn serve(config: &Config, app: Router) {
+  let (signaled, signal_received) = oneshot::channel();
+
+  tracing::info!(addr = %config.bind_addr, "listening");
+  let server = axum::Server::bind(&config.bind_addr)
+    .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+    .with_graceful_shutdown(async move {
+      shutdown::signal().await;
+      signaled.send(()).ok();
+    });
+
+  let deadline = async {
+    match signal_received.await {
+      Ok(()) => tokio::time::sleep(shutdown::remaining(config.shutdown_timeout)).await,
+      Err(_) => std::future::pending().await,
+    }
+  };
+
+  tokio::select! {
+    served = server => served.unwrap(),
+    _ = deadline => {
+      tracing::warn!(timeout = ?config.shutdown_timeout, "requests were still in flight, dropping them");
+    }
+  }
 }
 
 // Minnie
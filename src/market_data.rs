@@ -0,0 +1,154 @@
+pub mod coingecko;
+pub mod coinmarketcap;
+pub mod fixture;
+pub mod http;
+
+use crate::config::MarketDataConfig;
+use crate::database::redis_conn::RedisConn;
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+use utoipa::ToSchema;
+
+pub struct Asset {
+  pub cmc_id: i32,
+  // Symbols are not unique on CoinGecko, assets without their CoinGecko id are not quoted by it.
+  pub coingecko_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Quote {
+  pub price: Option<f64>,
+  pub market_cap: Option<f64>,
+  pub percent_change_24h: Option<f64>,
+  pub volume_24h: Option<f64>,
+  pub last_updated: String,
+}
+
+#[axum::async_trait]
+pub trait MarketDataProvider: Send + Sync {
+  fn name(&self) -> &'static str;
+
+  // Quotes in USD keyed by the `cmc_id` of the requested assets, missing assets are omitted.
+  async fn quotes(&self, assets: &[Asset]) -> Result<HashMap<i32, Quote>>;
+}
+
+pub struct FallbackProvider {
+  primary: Box<dyn MarketDataProvider>,
+  secondary: Box<dyn MarketDataProvider>,
+}
+
+#[axum::async_trait]
+impl MarketDataProvider for FallbackProvider {
+  fn name(&self) -> &'static str {
+    self.primary.name()
+  }
+
+  async fn quotes(&self, assets: &[Asset]) -> Result<HashMap<i32, Quote>> {
+    match self.primary.quotes(assets).await {
+      Ok(quotes) => Ok(quotes),
+      Err(err) => {
+        tracing::warn!(
+          primary = self.primary.name(),
+          secondary = self.secondary.name(),
+          error = %err,
+          "quotes failed, falling back"
+        );
+        self.secondary.quotes(assets).await
+      }
+    }
+  }
+}
+
+fn provider_by_name(
+  name: &str,
+  config: &MarketDataConfig,
+  redis_conn: &RedisConn,
+) -> Result<Box<dyn MarketDataProvider>> {
+  match name {
+    "coinmarketcap" => Ok(Box::new(coinmarketcap::CoinMarketCap::new(
+      &config.cmc,
+      redis_conn.clone(),
+    )?)),
+    "coingecko" => Ok(Box::new(coingecko::CoinGecko::new(&config.coingecko))),
+    "fixture" => Ok(Box::new(fixture::FixtureProvider::new(
+      &config.fixture_path,
+    )?)),
+    name => bail!("Unknown market data provider {name}"),
+  }
+}
+
+pub fn from_config(
+  config: &MarketDataConfig,
+  redis_conn: &RedisConn,
+) -> Result<Arc<dyn MarketDataProvider>> {
+  let primary = provider_by_name(&config.provider, config, redis_conn)?;
+
+  match &config.fallback {
+    Some(fallback) => Ok(Arc::new(FallbackProvider {
+      primary,
+      secondary: provider_by_name(fallback, config, redis_conn)?,
+    })),
+    None => Ok(Arc::from(primary)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use anyhow::anyhow;
+  use std::path::Path;
+
+  struct Unavailable;
+
+  #[axum::async_trait]
+  impl MarketDataProvider for Unavailable {
+    fn name(&self) -> &'static str {
+      "unavailable"
+    }
+
+    async fn quotes(&self, _assets: &[Asset]) -> Result<HashMap<i32, Quote>> {
+      Err(anyhow!("503 Service Unavailable"))
+    }
+  }
+
+  fn fixture() -> Box<dyn MarketDataProvider> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/market_data.json");
+    Box::new(fixture::FixtureProvider::new(&path).unwrap())
+  }
+
+  fn asset(cmc_id: i32) -> Asset {
+    Asset {
+      cmc_id,
+      coingecko_id: None,
+    }
+  }
+
+  #[tokio::test]
+  async fn falls_back_when_the_primary_fails() {
+    let provider = FallbackProvider {
+      primary: Box::new(Unavailable),
+      secondary: fixture(),
+    };
+
+    let quotes = provider
+      .quotes(&[asset(1), asset(1027), asset(999_999)])
+      .await
+      .unwrap();
+
+    assert_eq!(quotes.len(), 2);
+    assert_eq!(quotes[&1].price, Some(67012.45));
+    assert_eq!(quotes[&1027].percent_change_24h, Some(-0.62));
+  }
+
+  #[tokio::test]
+  async fn fails_when_both_providers_fail() {
+    let provider = FallbackProvider {
+      primary: Box::new(Unavailable),
+      secondary: Box::new(Unavailable),
+    };
+
+    assert!(provider.quotes(&[asset(1)]).await.is_err());
+  }
+}
@@ -0,0 +1,160 @@
+use super::{
+  http::{get_json, TokenBucket},
+  Asset, MarketDataProvider, Quote,
+};
+use crate::config::CoinGeckoConfig;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// CoinGecko has no notion of `cmc_id`, so assets are matched by the CoinGecko id stored next to it.
+pub struct CoinGecko {
+  base_url: String,
+  api_key: Option<String>,
+  limiter: TokenBucket,
+}
+
+impl CoinGecko {
+  pub fn new(config: &CoinGeckoConfig) -> Self {
+    Self {
+      base_url: config.base_url.to_owned(),
+      api_key: config.api_key.to_owned(),
+      limiter: TokenBucket::per_minute(config.rate_limit_per_minute),
+    }
+  }
+}
+
+#[derive(Serialize)]
+struct MarketsQuery {
+  vs_currency: &'static str,
+  ids: String,
+}
+
+#[derive(Deserialize)]
+struct Market {
+  id: String,
+  current_price: Option<f64>,
+  market_cap: Option<f64>,
+  price_change_percentage_24h: Option<f64>,
+  total_volume: Option<f64>,
+  last_updated: String,
+}
+
+#[axum::async_trait]
+impl MarketDataProvider for CoinGecko {
+  fn name(&self) -> &'static str {
+    "coingecko"
+  }
+
+  async fn quotes(&self, assets: &[Asset]) -> Result<HashMap<i32, Quote>> {
+    let ids: Vec<&str> = assets
+      .iter()
+      .filter_map(|asset| asset.coingecko_id.as_deref())
+      .collect();
+
+    if ids.is_empty() {
+      return Ok(HashMap::new());
+    }
+
+    let markets_query = MarketsQuery {
+      vs_currency: "usd",
+      ids: ids.join(","),
+    };
+
+    let url = format!("{}/coins/markets", self.base_url.trim_end_matches('/'));
+
+    let markets: Vec<Market> = get_json(self.name(), &self.limiter, || {
+      let mut request = surf::get(&url)
+        .query(&markets_query)
+        .map_err(surf::Error::into_inner)?;
+
+      if let Some(api_key) = &self.api_key {
+        request = request.header("x-cg-demo-api-key", api_key.as_str());
+      }
+
+      Ok(request)
+    })
+    .await?;
+
+    Ok(quotes_by_cmc_id(assets, &markets))
+  }
+}
+
+fn quotes_by_cmc_id(assets: &[Asset], markets: &[Market]) -> HashMap<i32, Quote> {
+  assets
+    .iter()
+    .filter_map(|asset| {
+      let coingecko_id = asset.coingecko_id.as_deref()?;
+
+      markets
+        .iter()
+        .find(|market| market.id == coingecko_id)
+        .map(|market| {
+          (
+            asset.cmc_id,
+            Quote {
+              price: market.current_price,
+              market_cap: market.market_cap,
+              percent_change_24h: market.price_change_percentage_24h,
+              volume_24h: market.total_volume,
+              last_updated: market.last_updated.to_owned(),
+            },
+          )
+        })
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Trimmed response of `/coins/markets?vs_currency=usd&ids=bitcoin,binancecoin`.
+  const MARKETS: &str = r#"[
+    {
+      "id": "bitcoin",
+      "symbol": "btc",
+      "current_price": 67012.45,
+      "market_cap": 1320145678901.12,
+      "price_change_percentage_24h": 1.84,
+      "total_volume": 28345123456.78,
+      "last_updated": "2024-01-01T00:00:00.000Z"
+    },
+    {
+      "id": "binancecoin",
+      "symbol": "bnb",
+      "current_price": 587.31,
+      "market_cap": null,
+      "price_change_percentage_24h": null,
+      "total_volume": 1634567890.12,
+      "last_updated": "2024-01-01T00:00:00.000Z"
+    }
+  ]"#;
+
+  fn asset(cmc_id: i32, coingecko_id: Option<&str>) -> Asset {
+    Asset {
+      cmc_id,
+      coingecko_id: coingecko_id.map(str::to_string),
+    }
+  }
+
+  #[test]
+  fn maps_markets_to_assets_by_coingecko_id() {
+    let markets: Vec<Market> = serde_json::from_str(MARKETS).unwrap();
+    let assets = [
+      asset(1, Some("bitcoin")),
+      asset(1839, Some("binancecoin")),
+      // Shares the `btc` symbol, but has no CoinGecko id.
+      asset(7777, None),
+      asset(1027, Some("ethereum")),
+    ];
+
+    let quotes = quotes_by_cmc_id(&assets, &markets);
+
+    assert_eq!(quotes.len(), 2);
+    assert_eq!(quotes[&1].price, Some(67012.45));
+    assert_eq!(quotes[&1].volume_24h, Some(28345123456.78));
+    assert_eq!(quotes[&1839].market_cap, None);
+    assert_eq!(quotes[&1839].last_updated, "2024-01-01T00:00:00.000Z");
+  }
+}
@@ -0,0 +1,198 @@
+use super::{
+  http::{get_json, TokenBucket},
+  Asset, MarketDataProvider, Quote,
+};
+use crate::config::CmcConfig;
+use crate::database::redis_conn::RedisConn;
+use anyhow::{anyhow, bail, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::HashMap,
+  sync::atomic::{AtomicU32, Ordering},
+};
+
+// Credit counters outlive their day a little so the previous day can still be inspected.
+const CREDITS_TTL_SECONDS: usize = 2 * 24 * 60 * 60;
+// The quotes endpoint costs one credit per 100 requested ids.
+const IDS_PER_CREDIT: usize = 100;
+
+pub struct CoinMarketCap {
+  base_url: String,
+  api_key: String,
+  daily_credit_budget: i64,
+  limiter: TokenBucket,
+  redis_conn: RedisConn,
+}
+
+impl CoinMarketCap {
+  pub fn new(config: &CmcConfig, redis_conn: RedisConn) -> Result<Self> {
+    Ok(Self {
+      base_url: config.base_url.to_owned(),
+      api_key: config
+        .api_key
+        .to_owned()
+        .ok_or_else(|| anyhow!("CMC_KEY is not set"))?,
+      daily_credit_budget: config.daily_credit_budget,
+      limiter: TokenBucket::per_minute(config.rate_limit_per_minute),
+      redis_conn,
+    })
+  }
+
+  fn credits_key() -> String {
+    format!("cmc:credits:{}", Utc::now().format("%Y-%m-%d"))
+  }
+
+  #[tracing::instrument(skip_all)]
+  async fn credits_used(&self) -> Result<i64> {
+    Ok(
+      redis::cmd("GET")
+        .arg(Self::credits_key())
+        .query_async::<_, Option<i64>>(&mut self.redis_conn.clone())
+        .await?
+        .unwrap_or_default(),
+    )
+  }
+
+  #[tracing::instrument(skip(self))]
+  async fn spend_credits(&self, credits: i64) -> Result<i64> {
+    let (used,): (i64,) = redis::pipe()
+      .atomic()
+      .cmd("INCRBY")
+      .arg(Self::credits_key())
+      .arg(credits)
+      .cmd("EXPIRE")
+      .arg(Self::credits_key())
+      .arg(CREDITS_TTL_SECONDS)
+      .ignore()
+      .query_async(&mut self.redis_conn.clone())
+      .await?;
+
+    Ok(used)
+  }
+}
+
+#[derive(Serialize)]
+struct CmcQuery {
+  id: String,
+}
+
+#[derive(Deserialize)]
+struct CmcResponse {
+  status: CmcStatus,
+  #[serde(default)]
+  data: HashMap<String, CmcCurrency>,
+}
+
+#[derive(Deserialize)]
+struct CmcStatus {
+  error_code: i64,
+  error_message: Option<String>,
+  credit_count: i64,
+}
+
+#[derive(Deserialize)]
+struct CmcCurrency {
+  id: i32,
+  quote: HashMap<String, CmcQuote>,
+}
+
+#[derive(Deserialize)]
+struct CmcQuote {
+  price: Option<f64>,
+  market_cap: Option<f64>,
+  percent_change_24h: Option<f64>,
+  volume_24h: Option<f64>,
+  last_updated: String,
+}
+
+#[axum::async_trait]
+impl MarketDataProvider for CoinMarketCap {
+  fn name(&self) -> &'static str {
+    "coinmarketcap"
+  }
+
+  async fn quotes(&self, assets: &[Asset]) -> Result<HashMap<i32, Quote>> {
+    let cmc_query = CmcQuery {
+      id: assets
+        .iter()
+        .map(|asset| asset.cmc_id.to_string())
+        .collect::<Vec<String>>()
+        .join(","),
+    };
+
+    let used = self.credits_used().await?;
+    let estimated = ((assets.len() + IDS_PER_CREDIT - 1) / IDS_PER_CREDIT) as i64;
+
+    if used + estimated > self.daily_credit_budget {
+      bail!(
+        "CMC daily credit budget is exhausted ({used}/{})",
+        self.daily_credit_budget
+      );
+    }
+
+    let url = format!(
+      "{}/v2/cryptocurrency/quotes/latest",
+      self.base_url.trim_end_matches('/')
+    );
+
+    let attempts = AtomicU32::new(0);
+    let response = get_json::<CmcResponse>(self.name(), &self.limiter, || {
+      attempts.fetch_add(1, Ordering::Relaxed);
+
+      surf::get(&url)
+        .header("X-CMC_PRO_API_KEY", self.api_key.as_str())
+        .query(&cmc_query)
+        .map_err(surf::Error::into_inner)
+    })
+    .await;
+
+    // Failed attempts do not tell what they were billed, each is charged the estimate of the request.
+    let failed_attempts = i64::from(attempts.into_inner()) - i64::from(response.is_ok());
+    let credits = failed_attempts * estimated
+      + response
+        .as_ref()
+        .map(|cmc_data| cmc_data.status.credit_count)
+        .unwrap_or_default();
+
+    let used = self.spend_credits(credits).await?;
+    tracing::info!(
+      ids = assets.len(),
+      credits,
+      used_today = used,
+      budget = self.daily_credit_budget,
+      "spent cmc credits"
+    );
+
+    let cmc_data = response?;
+
+    if cmc_data.status.error_code != 0 {
+      bail!(
+        "CMC responded with error {}: {}",
+        cmc_data.status.error_code,
+        cmc_data.status.error_message.unwrap_or_default()
+      );
+    }
+
+    Ok(
+      cmc_data
+        .data
+        .into_values()
+        .filter_map(|currency| {
+          currency.quote.get("USD").map(|usd| {
+            (
+              currency.id,
+              Quote {
+                price: usd.price,
+                market_cap: usd.market_cap,
+                percent_change_24h: usd.percent_change_24h,
+                volume_24h: usd.volume_24h,
+                last_updated: usd.last_updated.to_owned(),
+              },
+            )
+          })
+        })
+        .collect(),
+    )
+  }
+}
@@ -0,0 +1,37 @@
+use super::{Asset, MarketDataProvider, Quote};
+use anyhow::Result;
+use std::{collections::HashMap, fs, path::Path};
+
+// Serves quotes from a JSON file of `{ "<cmc_id>": Quote }` so the crawler can run offline.
+pub struct FixtureProvider {
+  quotes: HashMap<i32, Quote>,
+}
+
+impl FixtureProvider {
+  pub fn new(path: &Path) -> Result<Self> {
+    Ok(Self {
+      quotes: serde_json::from_str(&fs::read_to_string(path)?)?,
+    })
+  }
+}
+
+#[axum::async_trait]
+impl MarketDataProvider for FixtureProvider {
+  fn name(&self) -> &'static str {
+    "fixture"
+  }
+
+  async fn quotes(&self, assets: &[Asset]) -> Result<HashMap<i32, Quote>> {
+    Ok(
+      assets
+        .iter()
+        .filter_map(|asset| {
+          self
+            .quotes
+            .get(&asset.cmc_id)
+            .map(|quote| (asset.cmc_id, quote.clone()))
+        })
+        .collect(),
+    )
+  }
+}
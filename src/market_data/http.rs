@@ -0,0 +1,111 @@
+use anyhow::{bail, Result};
+use serde::de::DeserializeOwned;
+use std::time::{Duration, Instant};
+use surf::StatusCode;
+use tokio::sync::Mutex;
+
+const MAX_ATTEMPTS: u32 = 4;
+const BASE_BACKOFF: Duration = Duration::from_millis(500);
+
+// Spreads the requests of a provider over time so that bursts stay below the plan limit.
+pub struct TokenBucket {
+  capacity: f64,
+  refill_per_second: f64,
+  state: Mutex<BucketState>,
+}
+
+struct BucketState {
+  tokens: f64,
+  refilled_at: Instant,
+}
+
+impl TokenBucket {
+  pub fn per_minute(requests: u32) -> Self {
+    let capacity = requests.max(1) as f64;
+
+    Self {
+      capacity,
+      refill_per_second: capacity / 60.0,
+      state: Mutex::new(BucketState {
+        tokens: capacity,
+        refilled_at: Instant::now(),
+      }),
+    }
+  }
+
+  pub async fn acquire(&self) {
+    loop {
+      let wait = {
+        let mut state = self.state.lock().await;
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.refilled_at).as_secs_f64();
+
+        state.tokens = (state.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        state.refilled_at = now;
+
+        if state.tokens >= 1.0 {
+          state.tokens -= 1.0;
+          return;
+        }
+
+        Duration::from_secs_f64((1.0 - state.tokens) / self.refill_per_second)
+      };
+
+      tokio::time::sleep(wait).await;
+    }
+  }
+}
+
+fn retry_after(response: &surf::Response) -> Option<Duration> {
+  response
+    .header("Retry-After")
+    .and_then(|values| values.last().as_str().parse::<u64>().ok())
+    .map(Duration::from_secs)
+}
+
+// Sends the request built by `request` and retries with an exponential backoff on 429, 5xx and transport errors.
+#[tracing::instrument(skip(limiter, request))]
+pub async fn get_json<T: DeserializeOwned>(
+  provider: &str,
+  limiter: &TokenBucket,
+  request: impl Fn() -> Result<surf::RequestBuilder>,
+) -> Result<T> {
+  let mut attempt = 0;
+
+  loop {
+    attempt += 1;
+    limiter.acquire().await;
+
+    let backoff = BASE_BACKOFF * 2u32.pow(attempt - 1);
+
+    match request()?.await {
+      Ok(mut response) => {
+        let status = response.status();
+
+        if status.is_success() {
+          return response
+            .body_json::<T>()
+            .await
+            .map_err(surf::Error::into_inner);
+        }
+
+        let retryable = status == StatusCode::TooManyRequests || status.is_server_error();
+        if !retryable || attempt >= MAX_ATTEMPTS {
+          bail!("{provider} responded with status {status}");
+        }
+
+        let wait = retry_after(&response).unwrap_or(backoff);
+        tracing::warn!(provider, status = %status, ?wait, "provider responded with an error, retrying");
+        tokio::time::sleep(wait).await;
+      }
+      Err(err) => {
+        if attempt >= MAX_ATTEMPTS {
+          return Err(err.into_inner());
+        }
+
+        tracing::warn!(provider, ?backoff, error = %err, "provider request failed, retrying");
+        tokio::time::sleep(backoff).await;
+      }
+    }
+  }
+}
@@ -1,4 +1,47 @@
-use crate::services::{business::__path_get_businesses, user::__path_who_am_i};
+pub mod schemas;
+
+use crate::market_data::Quote;
+use crate::schedulers::registry::{JobRun, JobStatus, JobTrigger};
+use crate::services::{
+  auth::{AuthPayload, Tokens, __path_get_nonce, __path_login},
+  banner::{
+    ActiveBanner, CreateBannerPayload, UpdateBannerPayload, UpdateStoragePayload,
+    __path_create_banner, __path_delete_banner, __path_delete_storage, __path_get_all_banners,
+    __path_get_banners, __path_get_storages, __path_update_banner, __path_update_storage,
+    __path_upload_storage,
+  },
+  business::{
+    ChartRange, UpdateBusinessPayload, __path_get_business_chart, __path_get_businesses,
+    __path_update_business,
+  },
+  campaign::{
+    Allocation, AllocationsPayload, AllocationsResult, ClaimPayload, CreateCampaignPayload,
+    UpdateCampaignPayload, __path_allocate_campaign, __path_claim_campaign, __path_create_campaign,
+    __path_delete_campaign, __path_get_campaigns, __path_get_my_campaigns,
+    __path_update_campaign,
+  },
+  job::{__path_get_jobs, __path_run_job},
+  point::{LeaderboardPeriod, __path_get_leaderboard, __path_get_my_points},
+  health::{DependencyCheck, Readiness, __path_healthz, __path_readyz},
+  did::{
+    CreateDidPayload, LinkChallenge, LinkWalletPayload, TransferControllerPayload,
+    __path_create_did, __path_get_link_challenge, __path_link_wallet, __path_transfer_controller,
+    __path_unlink_wallet,
+  },
+  social::{
+    AuthorizeUrl, OAuthCallbackPayload, SocialPlatform, TelegramAuthPayload,
+    __path_authorize_social, __path_link_social, __path_link_telegram, __path_unlink_social,
+  },
+  user::{
+    UpdateProfilePayload, __path_update_me, __path_upload_avatar, __path_upload_background,
+    __path_who_am_i,
+  },
+  voucher::__path_get_voucher,
+};
+use schemas::{
+  BusinessMedia, BusinessWithQuote, DidMember, ErrorBody, ErrorDetail, Me, MeDid, RandBusiness,
+  UserClaims,
+};
 
 use utoipa::{
   openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
@@ -14,11 +57,90 @@ use utoipa::{
     contact (name = "thoanh098", url = "https://github.com/theanh098")
   ),
   paths(
+      healthz,
+      readyz,
+      get_nonce,
+      login,
       who_am_i,
+      update_me,
+      upload_avatar,
+      upload_background,
       get_businesses,
+      get_business_chart,
+      get_my_points,
+      get_leaderboard,
+      create_did,
+      get_link_challenge,
+      link_wallet,
+      unlink_wallet,
+      transfer_controller,
+      authorize_social,
+      link_social,
+      link_telegram,
+      unlink_social,
+      get_my_campaigns,
+      get_voucher,
+      claim_campaign,
+      get_campaigns,
+      create_campaign,
+      update_campaign,
+      delete_campaign,
+      allocate_campaign,
+      update_business,
+      get_banners,
+      get_all_banners,
+      create_banner,
+      update_banner,
+      delete_banner,
+      get_storages,
+      upload_storage,
+      update_storage,
+      delete_storage,
+      get_jobs,
+      run_job,
     ),
     components(
-      schemas(),
+      schemas(
+        ErrorBody,
+        ErrorDetail,
+        Readiness,
+        DependencyCheck,
+        AuthPayload,
+        Tokens,
+        UserClaims,
+        Me,
+        MeDid,
+        DidMember,
+        BusinessWithQuote,
+        RandBusiness,
+        BusinessMedia,
+        AuthorizeUrl,
+        AllocationsResult,
+        Quote,
+        ChartRange,
+        UpdateBusinessPayload,
+        LeaderboardPeriod,
+        CreateCampaignPayload,
+        UpdateCampaignPayload,
+        Allocation,
+        AllocationsPayload,
+        ClaimPayload,
+        SocialPlatform,
+        OAuthCallbackPayload,
+        TelegramAuthPayload,
+        UpdateProfilePayload,
+        CreateDidPayload,
+        LinkChallenge,
+        LinkWalletPayload,
+        TransferControllerPayload,
+        ActiveBanner,
+        CreateBannerPayload,
+        UpdateBannerPayload,
+        UpdateStoragePayload,
+        JobStatus,
+        JobRun,
+        JobTrigger
+      ),
       responses(App)
     ),
     modifiers(&BearerSecurity),
@@ -48,3 +170,28 @@ impl Modify for BearerSecurity {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fs;
+
+  const SNAPSHOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+
+  // Fails when a handler changes the public contract without `openapi.json` being regenerated,
+  // so that the change shows up in review. Regenerate with `cargo run -p openapi-cli`.
+  #[test]
+  fn openapi_matches_snapshot() {
+    let snapshot = fs::read_to_string(SNAPSHOT)
+      .unwrap_or_else(|_| panic!("{SNAPSHOT} is missing, run `cargo run -p openapi-cli`"));
+
+    let expected: serde_json::Value =
+      serde_json::from_str(&snapshot).expect("parsing the openapi snapshot was wrong");
+    let actual = serde_json::to_value(ApiDoc::openapi()).unwrap();
+
+    assert!(
+      expected == actual,
+      "the openapi document differs from {SNAPSHOT}, run `cargo run -p openapi-cli` and review the diff"
+    );
+  }
+}
@@ -0,0 +1,196 @@
+// Schemas of Prisma selections and error bodies, which cannot derive `ToSchema` themselves.
+// They only exist to be rendered in the OpenAPI document, the tests below keep them in line with the
+// serialized shape of the real types.
+#![allow(dead_code)]
+
+use crate::market_data::Quote;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(ToSchema)]
+pub struct UserClaims {
+  id: i32,
+  wallet_address: String,
+  is_admin: bool,
+}
+
+#[derive(ToSchema)]
+pub struct DidMember {
+  id: i32,
+  wallet_address: String,
+  nickname: Option<String>,
+  avatar_url: Option<String>,
+}
+
+#[derive(ToSchema)]
+pub struct MeDid {
+  id: i32,
+  controller: String,
+  email: Option<String>,
+  username: Option<String>,
+  users: Vec<DidMember>,
+}
+
+#[derive(ToSchema)]
+pub struct Me {
+  id: i32,
+  wallet_address: String,
+  avatar_url: Option<String>,
+  background_url: Option<String>,
+  email: Option<String>,
+  #[schema(format = DateTime)]
+  last_sync_ibt: Option<String>,
+  #[schema(format = DateTime)]
+  last_update: Option<String>,
+  nickname: Option<String>,
+  did: Option<MeDid>,
+}
+
+#[derive(ToSchema)]
+pub struct BusinessMedia {
+  url: String,
+}
+
+#[derive(ToSchema)]
+pub struct RandBusiness {
+  id: i32,
+  name: String,
+  types: Vec<String>,
+  overview: String,
+  logo: Option<String>,
+  main_category: String,
+  token: Option<String>,
+  cmc_id: Option<i32>,
+  medias: Vec<BusinessMedia>,
+}
+
+#[derive(ToSchema)]
+pub struct BusinessWithQuote {
+  id: i32,
+  name: String,
+  types: Vec<String>,
+  overview: String,
+  logo: Option<String>,
+  main_category: String,
+  token: Option<String>,
+  cmc_id: Option<i32>,
+  medias: Vec<BusinessMedia>,
+  quote: Option<Quote>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorDetail {
+  // Stable machine-readable code, e.g. `not_found` or `validation_failed`.
+  code: String,
+  message: String,
+  // Per-field validation errors under `fields`, keyed by path such as `allocations[0].amount`.
+  #[schema(value_type = Option<Object>)]
+  details: Option<serde_json::Value>,
+  request_id: Option<String>,
+}
+
+#[derive(ToSchema)]
+pub struct ErrorBody {
+  error: ErrorDetail,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::services::{auth::user_claims, business::rand_business, user::me};
+  use chrono::{DateTime, Utc};
+  use serde_json::Value;
+  use std::collections::BTreeSet;
+  use utoipa::openapi::{RefOr, Schema};
+
+  fn properties<'a, T: ToSchema<'a>>() -> BTreeSet<String> {
+    match T::schema() {
+      (_, RefOr::T(Schema::Object(object))) => object.properties.into_keys().collect(),
+      (name, _) => panic!("{name} is not an object schema"),
+    }
+  }
+
+  fn keys(value: impl Serialize) -> BTreeSet<String> {
+    match serde_json::to_value(value).unwrap() {
+      Value::Object(object) => object.into_keys().collect(),
+      value => panic!("{value} is not an object"),
+    }
+  }
+
+  fn member() -> me::did::users::Data {
+    me::did::users::Data {
+      id: 2,
+      wallet_address: "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2".to_string(),
+      nickname: Some("alice".to_string()),
+      avatar_url: None,
+    }
+  }
+
+  fn did() -> me::did::Data {
+    me::did::Data {
+      id: 1,
+      controller: "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2".to_string(),
+      email: None,
+      username: Some("alice".to_string()),
+      users: vec![member()],
+    }
+  }
+
+  fn media() -> rand_business::medias::Data {
+    rand_business::medias::Data {
+      url: "https://cdn.example.com/media.png".to_string(),
+    }
+  }
+
+  fn business() -> rand_business::Data {
+    rand_business::Data {
+      id: 1,
+      name: "Bitcoin".to_string(),
+      types: vec!["token".to_string()],
+      overview: "Peer-to-peer electronic cash".to_string(),
+      logo: None,
+      main_category: "crypto".to_string(),
+      token: Some("BTC".to_string()),
+      cmc_id: Some(1),
+      medias: vec![media()],
+    }
+  }
+
+  #[test]
+  fn user_schemas_follow_their_selections() {
+    let synced_at = DateTime::<Utc>::default().into();
+    let me = me::Data {
+      id: 2,
+      wallet_address: "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2".to_string(),
+      avatar_url: None,
+      background_url: None,
+      email: None,
+      last_sync_ibt: Some(synced_at),
+      last_update: None,
+      nickname: Some("alice".to_string()),
+      did: Some(did()),
+    };
+    let claims = user_claims::Data {
+      id: 2,
+      wallet_address: "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2".to_string(),
+      is_admin: false,
+    };
+
+    assert_eq!(keys(&me), properties::<Me>());
+    assert_eq!(keys(did()), properties::<MeDid>());
+    assert_eq!(keys(member()), properties::<DidMember>());
+    assert_eq!(keys(&claims), properties::<UserClaims>());
+  }
+
+  #[test]
+  fn business_schemas_follow_their_selections() {
+    // `services::business::BusinessWithQuote` flattens the selection next to its quote.
+    let mut with_quote = keys(business());
+    with_quote.insert("quote".to_string());
+
+    assert_eq!(keys(business()), properties::<RandBusiness>());
+    assert_eq!(keys(media()), properties::<BusinessMedia>());
+    assert_eq!(with_quote, properties::<BusinessWithQuote>());
+  }
+}
@@ -0,0 +1,249 @@
+use crate::intercept::{
+  cors,
+  rate_limit::{self, RateLimit},
+};
+use crate::{services, AppState};
+use axum::{
+  extract::DefaultBodyLimit,
+  handler::Handler,
+  middleware,
+  routing::{on, MethodFilter},
+  Router,
+};
+use utoipa::openapi::PathItemType;
+
+// Routes sharing a rate limit and a CORS policy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RouteGroup {
+  // Health checks, never limited.
+  Probe,
+  Auth,
+  Public,
+  User,
+  Admin,
+}
+
+impl RouteGroup {
+  pub fn name(self) -> &'static str {
+    match self {
+      RouteGroup::Probe => "probe",
+      RouteGroup::Auth => "auth",
+      RouteGroup::Public => "public",
+      RouteGroup::User => "user",
+      RouteGroup::Admin => "admin",
+    }
+  }
+
+  // Guarded groups are limited per user, the others per client IP.
+  pub fn is_guarded(self) -> bool {
+    matches!(self, RouteGroup::User | RouteGroup::Admin)
+  }
+}
+
+// Records every route it registers so that the OpenAPI document can be checked against the router.
+pub struct ApiRouter {
+  groups: Vec<(RouteGroup, Router<AppState>)>,
+  routes: Vec<(&'static str, PathItemType)>,
+}
+
+impl ApiRouter {
+  fn new() -> Self {
+    Self {
+      groups: vec![],
+      routes: vec![],
+    }
+  }
+
+  // Routes registered after this call belong to `group`.
+  fn group(mut self, group: RouteGroup) -> Self {
+    self.groups.push((group, Router::new()));
+    self
+  }
+
+  fn route<H, T>(
+    mut self,
+    method: PathItemType,
+    filter: MethodFilter,
+    path: &'static str,
+    handler: H,
+  ) -> Self
+  where
+    H: Handler<T, AppState>,
+    T: 'static,
+  {
+    let (group, router) = self.groups.pop().expect("routes are registered in a group");
+    self.routes.push((path, method));
+    self
+      .groups
+      .push((group, router.route(path, on(filter, handler))));
+    self
+  }
+
+  fn get<H: Handler<T, AppState>, T: 'static>(self, path: &'static str, handler: H) -> Self {
+    self.route(PathItemType::Get, MethodFilter::GET, path, handler)
+  }
+
+  fn post<H: Handler<T, AppState>, T: 'static>(self, path: &'static str, handler: H) -> Self {
+    self.route(PathItemType::Post, MethodFilter::POST, path, handler)
+  }
+
+  fn put<H: Handler<T, AppState>, T: 'static>(self, path: &'static str, handler: H) -> Self {
+    self.route(PathItemType::Put, MethodFilter::PUT, path, handler)
+  }
+
+  fn patch<H: Handler<T, AppState>, T: 'static>(self, path: &'static str, handler: H) -> Self {
+    self.route(PathItemType::Patch, MethodFilter::PATCH, path, handler)
+  }
+
+  fn delete<H: Handler<T, AppState>, T: 'static>(self, path: &'static str, handler: H) -> Self {
+    self.route(PathItemType::Delete, MethodFilter::DELETE, path, handler)
+  }
+
+  pub fn routes(&self) -> &[(&'static str, PathItemType)] {
+    &self.routes
+  }
+
+  pub fn into_router(self, state: &AppState) -> Router<AppState> {
+    self
+      .groups
+      .into_iter()
+      .fold(Router::new(), |app, (group, router)| {
+        let router = match rate_limit::policy(&state.config.rate_limit, group) {
+          Some(policy) => router.route_layer(middleware::from_fn_with_state(
+            RateLimit::new(group, policy, state),
+            rate_limit::rate_limit,
+          )),
+          None => router,
+        };
+
+        // Outside the rate limit, so that preflight requests are not counted.
+        let cors_policy = match group {
+          RouteGroup::Admin => &state.config.cors.admin,
+          _ => &state.config.cors.public,
+        };
+
+        app.merge(router.route_layer(cors::layer(cors_policy)))
+      })
+  }
+}
+
+// Served by every process, whatever its mode.
+pub fn probe_routes() -> ApiRouter {
+  ApiRouter::new()
+    .group(RouteGroup::Probe)
+    .get("/healthz", services::health::healthz)
+    .get("/readyz", services::health::readyz)
+}
+
+pub fn api_routes() -> ApiRouter {
+  ApiRouter::new()
+    .group(RouteGroup::Auth)
+    .get("/auth/nonce", services::auth::get_nonce)
+    .post("/auth/login", services::auth::login)
+    .group(RouteGroup::Public)
+    .get("/leaderboard", services::point::get_leaderboard)
+    .get("/businesses", services::business::get_businesses)
+    .get(
+      "/businesses/:id/chart",
+      services::business::get_business_chart,
+    )
+    .get("/banners", services::banner::get_banners)
+    .group(RouteGroup::User)
+    .get("/users", services::user::who_am_i)
+    .patch("/users/me", services::user::update_me)
+    .put(
+      "/users/me/avatar",
+      services::user::upload_avatar.layer(DefaultBodyLimit::max(services::user::MAX_IMAGE_BYTES)),
+    )
+    .put(
+      "/users/me/background",
+      services::user::upload_background
+        .layer(DefaultBodyLimit::max(services::user::MAX_IMAGE_BYTES)),
+    )
+    .get("/users/me/points", services::point::get_my_points)
+    .post("/dids", services::did::create_did)
+    .get("/dids/wallets/nonce", services::did::get_link_challenge)
+    .post("/dids/wallets", services::did::link_wallet)
+    .delete("/dids/wallets/:address", services::did::unlink_wallet)
+    .patch("/dids/controller", services::did::transfer_controller)
+    .post("/socials/telegram", services::social::link_telegram)
+    .delete("/socials/:platform", services::social::unlink_social)
+    .get(
+      "/socials/:platform/authorize",
+      services::social::authorize_social,
+    )
+    .post("/socials/:platform/callback", services::social::link_social)
+    .get("/campaigns", services::campaign::get_my_campaigns)
+    .get("/campaigns/:id/voucher", services::voucher::get_voucher)
+    .post("/campaigns/:id/claim", services::campaign::claim_campaign)
+    .group(RouteGroup::Admin)
+    .get("/admin/campaigns", services::campaign::get_campaigns)
+    .post("/admin/campaigns", services::campaign::create_campaign)
+    .patch("/admin/campaigns/:id", services::campaign::update_campaign)
+    .delete("/admin/campaigns/:id", services::campaign::delete_campaign)
+    .put(
+      "/admin/campaigns/:id/allocations",
+      services::campaign::allocate_campaign,
+    )
+    .patch("/admin/businesses/:id", services::business::update_business)
+    .get("/admin/banners", services::banner::get_all_banners)
+    .post("/admin/banners", services::banner::create_banner)
+    .patch("/admin/banners/:id", services::banner::update_banner)
+    .delete("/admin/banners/:id", services::banner::delete_banner)
+    .get("/admin/storages", services::banner::get_storages)
+    .post(
+      "/admin/storages",
+      services::banner::upload_storage
+        .layer(DefaultBodyLimit::max(services::user::MAX_IMAGE_BYTES)),
+    )
+    .patch("/admin/storages/:id", services::banner::update_storage)
+    .delete("/admin/storages/:id", services::banner::delete_storage)
+    .get("/admin/jobs", services::job::get_jobs)
+    .post("/admin/jobs/:name/run", services::job::run_job)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::open_api::ApiDoc;
+  use utoipa::OpenApi;
+
+  // `/campaigns/:id/claim` is documented as `/campaigns/{id}/claim`.
+  fn openapi_path(path: &str) -> String {
+    path
+      .split('/')
+      .map(|segment| match segment.strip_prefix(':') {
+        Some(param) => format!("{{{param}}}"),
+        None => segment.to_string(),
+      })
+      .collect::<Vec<String>>()
+      .join("/")
+  }
+
+  #[test]
+  fn every_route_has_an_openapi_path() {
+    let openapi = ApiDoc::openapi();
+
+    let (probe_routes, api_routes) = (probe_routes(), api_routes());
+
+    let undocumented: Vec<String> = probe_routes
+      .routes()
+      .iter()
+      .chain(api_routes.routes())
+      .filter(|(path, method)| {
+        !openapi
+          .paths
+          .paths
+          .get(&openapi_path(path))
+          .map(|item| item.operations.contains_key(method))
+          .unwrap_or(false)
+      })
+      .map(|(path, method)| format!("{method:?} {path}"))
+      .collect();
+
+    assert!(
+      undocumented.is_empty(),
+      "routes without an OpenAPI path: {undocumented:?}"
+    );
+  }
+}
@@ -1 +1,39 @@
+pub mod banner;
 pub mod cmc;
+pub mod lease;
+pub mod registry;
+pub mod snapshot;
+
+use anyhow::{bail, Result};
+use std::str::FromStr;
+
+// Lets the API and the scheduler be deployed as separate processes from the same binary.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ProcessMode {
+  Api,
+  Scheduler,
+  All,
+}
+
+impl FromStr for ProcessMode {
+  type Err = anyhow::Error;
+
+  fn from_str(mode: &str) -> Result<Self> {
+    match mode {
+      "all" => Ok(Self::All),
+      "api" => Ok(Self::Api),
+      "scheduler" => Ok(Self::Scheduler),
+      mode => bail!("unknown process mode {mode}"),
+    }
+  }
+}
+
+impl ProcessMode {
+  pub fn runs_api(self) -> bool {
+    self != Self::Scheduler
+  }
+
+  pub fn runs_scheduler(self) -> bool {
+    self != Self::Api
+  }
+}
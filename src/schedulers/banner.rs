@@ -0,0 +1,66 @@
+use super::registry::ScheduledJob;
+use crate::database::observe::ObserveQuery;
+use crate::database::prisma::{banner, PrismaClient};
+use crate::database::redis_conn::RedisConn;
+use crate::services::banner::invalidate_banner_cache;
+use anyhow::Result;
+use chrono::Utc;
+use std::{sync::Arc, time::Duration};
+
+pub struct BannerArchive {
+  prisma_client: Arc<PrismaClient>,
+  redis_conn: RedisConn,
+}
+
+impl BannerArchive {
+  pub fn new(prisma_client: Arc<PrismaClient>, redis_conn: RedisConn) -> Self {
+    Self {
+      prisma_client,
+      redis_conn,
+    }
+  }
+}
+
+#[axum::async_trait]
+impl ScheduledJob for BannerArchive {
+  fn name(&self) -> &'static str {
+    "banner_archive"
+  }
+
+  fn default_schedule(&self) -> &'static str {
+    "0 * * * * *"
+  }
+
+  fn timeout(&self) -> Duration {
+    Duration::from_secs(30)
+  }
+
+  async fn run(&self) -> Result<()> {
+    archive_expired_banners(&self.prisma_client, &mut self.redis_conn.clone()).await
+  }
+}
+
+pub async fn archive_expired_banners(
+  prisma_client: &PrismaClient,
+  redis_conn: &mut RedisConn,
+) -> Result<()> {
+  let archived = prisma_client
+    .banner()
+    .update_many(
+      vec![
+        banner::archived::equals(false),
+        banner::expried_time::lte(Utc::now().into()),
+      ],
+      vec![banner::archived::set(true)],
+    )
+    .exec()
+    .observe("banner.update_many")
+    .await?;
+
+  if archived > 0 {
+    tracing::info!(archived, "archived expired banners");
+    invalidate_banner_cache(redis_conn).await;
+  }
+
+  Ok(())
+}
@@ -1,40 +1,139 @@
+use super::{
+  registry::{RetryPolicy, ScheduledJob},
+  snapshot::record_snapshots,
+};
+use crate::database::observe::ObserveQuery;
 use crate::database::prisma::{business, PrismaClient};
+use crate::database::redis_conn::RedisConn;
+use crate::market_data::{Asset, MarketDataProvider, Quote};
 use anyhow::Result;
-use std::{collections::HashMap, env};
-use tokio_cron_scheduler::{Job, JobScheduler};
+use chrono::Utc;
+use serde::Serialize;
+use std::{
+  collections::HashMap,
+  sync::Arc,
+  time::{Duration, Instant},
+};
 
-#[axum::async_trait]
-pub trait CmcCrawling {
-  async fn crawl_cmc(&self) -> Result<()>;
+const QUOTE_TTL_SECONDS: usize = 60 * 60;
+const LAST_RUN_KEY: &str = "cmc:last_run";
+
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct CrawlRun {
+  provider: &'static str,
+  started_at: String,
+  duration_ms: u128,
+  pages: usize,
+  assets: usize,
+  quotes: usize,
+  error: Option<String>,
 }
 
-#[derive(serde::Serialize)]
-struct CmcQuery {
-  id: String,
+pub struct CmcCrawl {
+  prisma_client: Arc<PrismaClient>,
+  redis_conn: RedisConn,
+  market_data: Arc<dyn MarketDataProvider>,
 }
 
-#[derive(serde::Deserialize)]
-struct CmcResponse {
-  data: HashMap<String, serde_json::Value>,
+impl CmcCrawl {
+  pub fn new(
+    prisma_client: Arc<PrismaClient>,
+    redis_conn: RedisConn,
+    market_data: Arc<dyn MarketDataProvider>,
+  ) -> Self {
+    Self {
+      prisma_client,
+      redis_conn,
+      market_data,
+    }
+  }
+}
+
+fn quote_key(cmc_id: i32) -> String {
+  format!("cmc:quote:{cmc_id}")
 }
 
 #[axum::async_trait]
-impl CmcCrawling for JobScheduler {
-  async fn crawl_cmc(&self) -> Result<()> {
-    self
-      .add(Job::new("1/10 * * * * *", |_uuid, _l| {
-        println!("I run every 10 seconds");
-      })?)
-      .await?;
+impl ScheduledJob for CmcCrawl {
+  fn name(&self) -> &'static str {
+    "cmc_crawl"
+  }
+
+  fn default_schedule(&self) -> &'static str {
+    "0 */5 * * * *"
+  }
+
+  fn timeout(&self) -> Duration {
+    Duration::from_secs(4 * 60)
+  }
+
+  // Requests are already retried by the provider, a second attempt only covers database and Redis hiccups.
+  fn retry_policy(&self) -> RetryPolicy {
+    RetryPolicy {
+      max_attempts: 2,
+      backoff: Duration::from_secs(30),
+    }
+  }
+
+  async fn run(&self) -> Result<()> {
+    let mut redis_conn = self.redis_conn.clone();
+    let market_data = self.market_data.as_ref();
+    let started = Instant::now();
+    let mut run = CrawlRun {
+      provider: market_data.name(),
+      started_at: Utc::now().to_rfc3339(),
+      ..Default::default()
+    };
+
+    let crawled =
+      crawl_cryptocurrency_quotes(&self.prisma_client, &mut redis_conn, market_data, &mut run)
+        .await;
 
-    Ok(())
+    run.duration_ms = started.elapsed().as_millis();
+    run.error = crawled.as_ref().err().map(|err| err.to_string());
+    metrics::increment_counter!(
+      "cmc_crawls_total",
+      "provider" => run.provider,
+      "outcome" => if crawled.is_ok() { "success" } else { "failure" },
+    );
+    metrics::counter!("cmc_quotes_crawled_total", run.quotes as u64, "provider" => run.provider);
+    tracing::info!(
+      provider = run.provider,
+      assets = run.assets,
+      pages = run.pages,
+      quotes = run.quotes,
+      duration_ms = run.duration_ms as u64,
+      "crawled quotes"
+    );
+
+    if let Err(err) = record_run(&mut redis_conn, &run).await {
+      tracing::warn!(error = %err, "recording the crawl run failed");
+    }
+
+    crawled
   }
 }
 
+// Keeps the outcome of the latest run around so that quota burn can be inspected without the logs.
+#[tracing::instrument(skip_all)]
+async fn record_run(redis_conn: &mut RedisConn, run: &CrawlRun) -> Result<()> {
+  redis::cmd("SET")
+    .arg(LAST_RUN_KEY)
+    .arg(serde_json::to_string(run)?)
+    .query_async::<_, ()>(redis_conn)
+    .await?;
+
+  Ok(())
+}
+
+#[tracing::instrument(name = "cmc_crawl", skip_all, fields(provider = market_data.name()))]
 async fn crawl_cryptocurrency_quotes(
   prisma_client: &PrismaClient,
-  redis_client: &redis::Client,
-) -> Result<(), surf::Error> {
+  redis_conn: &mut RedisConn,
+  market_data: &dyn MarketDataProvider,
+  run: &mut CrawlRun,
+) -> Result<()> {
   let mut i = 1;
   let chunk_size = 50;
 
@@ -48,33 +147,81 @@ async fn crawl_cryptocurrency_quotes(
       .order_by(business::id::order(prisma_client_rust::Direction::Asc))
       .take(chunk_size)
       .skip((i - 1) * chunk_size)
-      .select(business::select!({ cmc_id }))
+      .select(business::select!({ id cmc_id coingecko_id }))
       .exec()
+      .observe("business.find_many")
       .await?;
 
     if businesses.is_empty() {
       return Ok(());
     }
 
-    let cmc_query = CmcQuery {
-      id: businesses
-        .iter()
-        .map(|b| b.cmc_id.unwrap().to_string())
-        .collect::<Vec<String>>()
-        .join(","),
-    };
+    let assets: Vec<Asset> = businesses
+      .iter()
+      .filter_map(|b| {
+        b.cmc_id.map(|cmc_id| Asset {
+          cmc_id,
+          coingecko_id: b.coingecko_id.to_owned(),
+        })
+      })
+      .collect();
 
-    let cmc_data = surf::get("https://pro-api.coinmarketcap.com/v2/cryptocurrency/quotes/latest")
-      .header(
-        "X-CMC_PRO_API_KEY",
-        env::var("CMC_KEY").expect("CMC_KEY must be set"),
-      )
-      .query(&cmc_query)
-      .unwrap()
-      .await?
-      .body_json::<CmcResponse>()
-      .await?;
+    let quotes = market_data.quotes(&assets).await?;
+    run.pages += 1;
+    run.assets += assets.len();
+    run.quotes += quotes.len();
+
+    let mut pipe = redis::pipe();
 
-    cmc_query.id.s
+    for (cmc_id, quote) in quotes.iter() {
+      pipe
+        .cmd("SET")
+        .arg(quote_key(*cmc_id))
+        .arg(serde_json::to_string(quote)?)
+        .arg("EX")
+        .arg(QUOTE_TTL_SECONDS)
+        .ignore();
+    }
+
+    pipe.query_async::<_, ()>(redis_conn).await?;
+
+    let business_quotes: Vec<(i32, Quote)> = businesses
+      .iter()
+      .filter_map(|b| {
+        b.cmc_id
+          .and_then(|cmc_id| quotes.get(&cmc_id))
+          .map(|quote| (b.id, quote.clone()))
+      })
+      .collect();
+    record_snapshots(prisma_client, &business_quotes).await?;
+
+    i += 1;
   }
 }
+
+#[tracing::instrument(skip_all, fields(ids = cmc_ids.len()))]
+pub async fn latest_quotes(
+  redis_conn: &mut RedisConn,
+  cmc_ids: &[i32],
+) -> Result<HashMap<i32, Quote>> {
+  if cmc_ids.is_empty() {
+    return Ok(HashMap::new());
+  }
+
+  let cached: Vec<Option<String>> = redis::cmd("MGET")
+    .arg(cmc_ids.iter().map(|id| quote_key(*id)).collect::<Vec<String>>())
+    .query_async(redis_conn)
+    .await?;
+
+  Ok(
+    cmc_ids
+      .iter()
+      .zip(cached)
+      .filter_map(|(cmc_id, quote)| {
+        quote
+          .and_then(|quote| serde_json::from_str::<Quote>(&quote).ok())
+          .map(|quote| (*cmc_id, quote))
+      })
+      .collect(),
+  )
+}
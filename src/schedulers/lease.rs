@@ -0,0 +1,137 @@
+use crate::database::redis_conn::RedisConn;
+use crate::utils::random_string;
+use anyhow::{bail, Result};
+use std::{future::Future, time::Duration};
+use tokio::time::Instant;
+
+const RENEW_LEASE_SCRIPT: &str = r#"
+  if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("PEXPIRE", KEYS[1], ARGV[2])
+  end
+  return 0
+"#;
+
+const RELEASE_LEASE_SCRIPT: &str = r#"
+  if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+  end
+  return 0
+"#;
+
+// A lease held by a single instance, identified by a random token so that nobody else can renew or release it.
+pub struct Lease {
+  redis_conn: RedisConn,
+  key: String,
+  token: String,
+  ttl: Duration,
+}
+
+impl Lease {
+  #[tracing::instrument(skip(redis_conn))]
+  pub async fn acquire(redis_conn: &RedisConn, name: &str, ttl: Duration) -> Result<Option<Self>> {
+    let mut redis_conn = redis_conn.clone();
+    let key = format!("lease:{name}");
+    let token = random_string(32);
+
+    let acquired: Option<String> = redis::cmd("SET")
+      .arg(&key)
+      .arg(&token)
+      .arg("NX")
+      .arg("PX")
+      .arg(ttl.as_millis() as u64)
+      .query_async(&mut redis_conn)
+      .await?;
+
+    Ok(acquired.map(|_| Self {
+      redis_conn,
+      key,
+      token,
+      ttl,
+    }))
+  }
+
+  pub async fn renew(&mut self) -> Result<bool> {
+    let renewed: i64 = redis::Script::new(RENEW_LEASE_SCRIPT)
+      .key(&self.key)
+      .arg(&self.token)
+      .arg(self.ttl.as_millis() as u64)
+      .invoke_async(&mut self.redis_conn)
+      .await?;
+
+    Ok(renewed == 1)
+  }
+
+  pub async fn release(mut self) -> Result<()> {
+    redis::Script::new(RELEASE_LEASE_SCRIPT)
+      .key(&self.key)
+      .arg(&self.token)
+      .invoke_async::<_, i64>(&mut self.redis_conn)
+      .await?;
+
+    Ok(())
+  }
+}
+
+// Claims the run of `name` scheduled at `slot`, a unix timestamp, for one instance. The claim is never
+// released, it expires after `ttl`, so that an instance whose clock is behind cannot run the slot again
+// once the first run is done.
+#[tracing::instrument(skip(redis_conn))]
+pub async fn claim_slot(
+  redis_conn: &RedisConn,
+  name: &str,
+  slot: i64,
+  ttl: Duration,
+) -> Result<bool> {
+  let claimed: Option<String> = redis::cmd("SET")
+    .arg(format!("lease:{name}:{slot}"))
+    .arg(random_string(32))
+    .arg("NX")
+    .arg("PX")
+    .arg(ttl.as_millis() as u64)
+    .query_async(&mut redis_conn.clone())
+    .await?;
+
+  Ok(claimed.is_some())
+}
+
+// Runs `job` only on the instance that wins the lease of `name`, renewing it until the job is done.
+// Returns `None` when another instance is already running the job. The job is dropped as soon as the
+// lease is lost, another instance may have taken it over by then.
+pub async fn run_exclusive<T>(
+  redis_conn: &RedisConn,
+  name: &str,
+  ttl: Duration,
+  job: impl Future<Output = T>,
+) -> Result<Option<T>> {
+  let mut lease = match Lease::acquire(redis_conn, name, ttl).await? {
+    Some(lease) => lease,
+    None => return Ok(None),
+  };
+
+  tokio::pin!(job);
+  let mut renewal = tokio::time::interval(ttl / 3);
+  // The first tick completes immediately and the lease is fresh anyway.
+  renewal.tick().await;
+  let mut renewed_at = Instant::now();
+
+  let output = loop {
+    tokio::select! {
+      output = &mut job => break output,
+      _ = renewal.tick() => match lease.renew().await {
+        Ok(true) => renewed_at = Instant::now(),
+        Ok(false) => bail!("lease {name} expired while its job was running, the job was aborted"),
+        // Redis may only be slow, the lease is given up once it has certainly expired.
+        Err(err) if renewed_at.elapsed() >= ttl => {
+          bail!("lease {name} could not be renewed before it expired, the job was aborted: {err}")
+        }
+        Err(err) => tracing::warn!(lease = name, error = %err, "renewing lease failed"),
+      },
+    }
+  };
+
+  if let Err(err) = lease.release().await {
+    tracing::warn!(lease = name, error = %err, "releasing lease failed");
+  }
+
+  Ok(Some(output))
+}
@@ -0,0 +1,341 @@
+use super::lease::{claim_slot, run_exclusive};
+use crate::database::redis_conn::RedisConn;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::{BTreeMap, HashMap},
+  str::FromStr,
+  sync::Arc,
+  time::{Duration, Instant},
+};
+use tokio_cron_scheduler::{Job, JobScheduler};
+use tokio_util::task::TaskTracker;
+use utoipa::ToSchema;
+
+const JOB_RUNS_KEY: &str = "jobs:last_run";
+const LEASE_TTL: Duration = Duration::from_secs(30);
+// Outlives the clock drift between instances by far, a slot claimed once cannot be claimed again.
+const SLOT_CLAIM_TTL: Duration = Duration::from_secs(60 * 60);
+// How late the scheduler may fire a run and still have it attributed to its slot.
+const SLOT_LOOKBACK_SECONDS: i64 = 60;
+
+#[derive(Clone, Copy)]
+pub struct RetryPolicy {
+  pub max_attempts: u32,
+  // Waited after the first failed attempt, doubled after each following one.
+  pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    Self {
+      max_attempts: 1,
+      backoff: Duration::from_secs(5),
+    }
+  }
+}
+
+#[axum::async_trait]
+pub trait ScheduledJob: Send + Sync {
+  fn name(&self) -> &'static str;
+
+  // Cron expression used unless the config overrides the schedule of this job.
+  fn default_schedule(&self) -> &'static str;
+
+  fn timeout(&self) -> Duration;
+
+  fn retry_policy(&self) -> RetryPolicy {
+    RetryPolicy::default()
+  }
+
+  async fn run(&self) -> Result<()>;
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum JobTrigger {
+  Schedule,
+  Manual,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct JobRun {
+  trigger: JobTrigger,
+  started_at: String,
+  duration_ms: u64,
+  attempts: u32,
+  error: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct JobStatus {
+  name: String,
+  schedule: String,
+  timeout_seconds: u64,
+  max_attempts: u32,
+  last_run: Option<JobRun>,
+}
+
+struct RegisteredJob {
+  job: Arc<dyn ScheduledJob>,
+  schedule: String,
+}
+
+pub struct JobRegistry {
+  jobs: BTreeMap<&'static str, RegisteredJob>,
+  schedules: HashMap<String, String>,
+  redis_conn: RedisConn,
+  // Scheduled and manual runs, so that a shutdown can wait for them.
+  runs: TaskTracker,
+}
+
+impl JobRegistry {
+  pub fn new(redis_conn: RedisConn, schedules: HashMap<String, String>) -> Self {
+    Self {
+      jobs: BTreeMap::new(),
+      schedules,
+      redis_conn,
+      runs: TaskTracker::new(),
+    }
+  }
+
+  pub fn register(mut self, job: impl ScheduledJob + 'static) -> Self {
+    let schedule = self
+      .schedules
+      .remove(job.name())
+      .unwrap_or_else(|| job.default_schedule().to_string());
+
+    self.jobs.insert(
+      job.name(),
+      RegisteredJob {
+        job: Arc::new(job),
+        schedule,
+      },
+    );
+
+    self
+  }
+
+  // Fails on schedule overrides left over after every job was registered, a typo in `JOB_<NAME>_CRON`
+  // would otherwise keep the default schedule without anybody noticing.
+  pub fn finish(self) -> Result<Self> {
+    if !self.schedules.is_empty() {
+      let mut unknown: Vec<String> = self
+        .schedules
+        .keys()
+        .map(|name| format!("JOB_{}_CRON", name.to_uppercase()))
+        .collect();
+      unknown.sort();
+
+      bail!("{} name no registered job", unknown.join(", "));
+    }
+
+    Ok(self)
+  }
+
+  pub async fn schedule(&self, sched: &JobScheduler) -> Result<()> {
+    for registered in self.jobs.values() {
+      let job = registered.job.clone();
+      let redis_conn = self.redis_conn.clone();
+      let cron = Schedule::from_str(&registered.schedule)?;
+      let runs = self.runs.clone();
+
+      sched
+        .add(Job::new_async(
+          registered.schedule.as_str(),
+          move |_uuid, _l| {
+            let job = job.clone();
+            let redis_conn = redis_conn.clone();
+            let slot = last_slot(&cron, Utc::now());
+
+            // Without its slot the run cannot be deduplicated across instances, a tick this late is
+            // skipped rather than risking a second run, the next one catches up.
+            Box::pin(runs.track_future(async move {
+              match slot {
+                Some(slot) => {
+                  execute(job.as_ref(), &redis_conn, JobTrigger::Schedule, Some(slot)).await
+                }
+                None => tracing::warn!(job = job.name(), "scheduled tick fired too late, skipping"),
+              }
+            }))
+          },
+        )?)
+        .await?;
+    }
+
+    Ok(())
+  }
+
+  // Starts the job in the background, returns false when no job has this name.
+  pub fn trigger(&self, name: &str) -> bool {
+    match self.jobs.get(name) {
+      Some(registered) => {
+        let job = registered.job.clone();
+        let redis_conn = self.redis_conn.clone();
+
+        self.runs.spawn(async move {
+          execute(job.as_ref(), &redis_conn, JobTrigger::Manual, None).await;
+        });
+
+        true
+      }
+      None => false,
+    }
+  }
+
+  // Waits at most `timeout` for the runs in flight, to be called once the scheduler has stopped.
+  // Returns false when some were still running.
+  pub async fn drain(&self, timeout: Duration) -> bool {
+    self.runs.close();
+    tokio::time::timeout(timeout, self.runs.wait())
+      .await
+      .is_ok()
+  }
+
+  pub async fn statuses(&self) -> Result<Vec<JobStatus>> {
+    let mut last_runs: HashMap<String, String> = redis::cmd("HGETALL")
+      .arg(JOB_RUNS_KEY)
+      .query_async(&mut self.redis_conn.clone())
+      .await?;
+
+    Ok(
+      self
+        .jobs
+        .iter()
+        .map(|(name, registered)| JobStatus {
+          name: name.to_string(),
+          schedule: registered.schedule.to_owned(),
+          timeout_seconds: registered.job.timeout().as_secs(),
+          max_attempts: registered.job.retry_policy().max_attempts,
+          last_run: last_runs
+            .remove(*name)
+            .and_then(|run| serde_json::from_str(&run).ok()),
+        })
+        .collect(),
+    )
+  }
+}
+
+// Unix timestamp of the slot the scheduler fires at `now`, the same on every instance as long as their
+// clocks are within `SLOT_LOOKBACK_SECONDS` of each other.
+fn last_slot(cron: &Schedule, now: DateTime<Utc>) -> Option<i64> {
+  cron
+    .after(&(now - chrono::Duration::seconds(SLOT_LOOKBACK_SECONDS)))
+    .take_while(|slot| *slot <= now)
+    .last()
+    .map(|slot| slot.timestamp())
+}
+
+// Scheduled runs claim their slot first, manual runs only take the lease.
+#[tracing::instrument(skip_all, fields(job = job.name(), slot))]
+async fn execute(
+  job: &dyn ScheduledJob,
+  redis_conn: &RedisConn,
+  trigger: JobTrigger,
+  slot: Option<i64>,
+) {
+  let name = job.name();
+
+  if let Some(slot) = slot {
+    tracing::Span::current().record("slot", slot);
+
+    match claim_slot(redis_conn, name, slot, SLOT_CLAIM_TTL).await {
+      Ok(true) => {}
+      Ok(false) => {
+        tracing::info!("job already ran for this slot on another instance, skipping");
+        return;
+      }
+      Err(err) => {
+        tracing::error!(error = %err, "claiming the job slot failed");
+        return;
+      }
+    }
+  }
+
+  match run_exclusive(redis_conn, name, LEASE_TTL, run_with_retries(job, trigger)).await {
+    Ok(Some(run)) => {
+      match &run.error {
+        Some(err) => tracing::error!(attempts = run.attempts, error = %err, "job failed"),
+        None => tracing::info!(duration_ms = run.duration_ms, "job finished"),
+      }
+
+      if let Err(err) = record_run(redis_conn, name, &run).await {
+        tracing::warn!(error = %err, "recording the job run failed");
+      }
+    }
+    Ok(None) => tracing::info!("job is running on another instance, skipping"),
+    Err(err) => tracing::error!(error = %err, "running the job under its lease failed"),
+  }
+}
+
+async fn run_with_retries(job: &dyn ScheduledJob, trigger: JobTrigger) -> JobRun {
+  let started_at = Utc::now().to_rfc3339();
+  let started = Instant::now();
+  let policy = job.retry_policy();
+  let max_attempts = policy.max_attempts.max(1);
+
+  let mut attempts = 0;
+  let mut error = None;
+
+  while attempts < max_attempts {
+    attempts += 1;
+
+    error = match tokio::time::timeout(job.timeout(), job.run()).await {
+      Ok(Ok(())) => None,
+      Ok(Err(err)) => Some(err.to_string()),
+      Err(_) => Some(format!("timed out after {:?}", job.timeout())),
+    };
+
+    match &error {
+      None => break,
+      Some(err) if attempts < max_attempts => {
+        let backoff = policy.backoff * 2u32.pow(attempts - 1);
+        tracing::warn!(attempts, ?backoff, error = %err, "job attempt failed, retrying");
+        tokio::time::sleep(backoff).await;
+      }
+      Some(_) => {}
+    }
+  }
+
+  JobRun {
+    trigger,
+    started_at,
+    duration_ms: started.elapsed().as_millis() as u64,
+    attempts,
+    error,
+  }
+}
+
+#[tracing::instrument(skip(redis_conn, run))]
+async fn record_run(redis_conn: &RedisConn, name: &str, run: &JobRun) -> Result<()> {
+  redis::cmd("HSET")
+    .arg(JOB_RUNS_KEY)
+    .arg(name)
+    .arg(serde_json::to_string(run)?)
+    .query_async::<_, ()>(&mut redis_conn.clone())
+    .await?;
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::TimeZone;
+
+  #[test]
+  fn runs_are_attributed_to_the_slot_they_were_scheduled_for() {
+    let cron = Schedule::from_str("0 */10 * * * *").unwrap();
+    let slot = Utc.with_ymd_and_hms(2024, 1, 1, 12, 20, 0).unwrap();
+
+    for fired_at in [slot, slot + chrono::Duration::milliseconds(800)] {
+      assert_eq!(last_slot(&cron, fired_at), Some(slot.timestamp()));
+    }
+
+    assert_eq!(last_slot(&cron, slot + chrono::Duration::minutes(5)), None);
+  }
+}
@@ -0,0 +1,201 @@
+use super::registry::{RetryPolicy, ScheduledJob};
+use crate::database::observe::ObserveQuery;
+use crate::database::prisma::PrismaClient;
+use crate::market_data::Quote;
+use anyhow::Result;
+use chrono::{DateTime, Duration, DurationRound, FixedOffset, Utc};
+use prisma_client_rust::{raw, PrismaValue, Raw};
+use std::sync::Arc;
+
+const FIVE_MINUTES_RETENTION_DAYS: i64 = 2;
+const ONE_HOUR_RETENTION_DAYS: i64 = 30;
+
+pub struct SnapshotRollup {
+  prisma_client: Arc<PrismaClient>,
+}
+
+impl SnapshotRollup {
+  pub fn new(prisma_client: Arc<PrismaClient>) -> Self {
+    Self { prisma_client }
+  }
+}
+
+#[axum::async_trait]
+impl ScheduledJob for SnapshotRollup {
+  fn name(&self) -> &'static str {
+    "snapshot_rollup"
+  }
+
+  fn default_schedule(&self) -> &'static str {
+    "0 1 * * * *"
+  }
+
+  fn timeout(&self) -> std::time::Duration {
+    std::time::Duration::from_secs(10 * 60)
+  }
+
+  // Rollups rebuild whole buckets, so running them again is harmless.
+  fn retry_policy(&self) -> RetryPolicy {
+    RetryPolicy {
+      max_attempts: 3,
+      backoff: std::time::Duration::from_secs(60),
+    }
+  }
+
+  async fn run(&self) -> Result<()> {
+    downsample_snapshots(&self.prisma_client).await
+  }
+}
+
+// Folds the quotes into the current 5 minutes bucket of each business.
+#[tracing::instrument(skip_all, fields(quotes = quotes.len()))]
+pub async fn record_snapshots(
+  prisma_client: &PrismaClient,
+  quotes: &[(i32, Quote)],
+) -> Result<()> {
+  let quotes: Vec<&(i32, Quote)> = quotes.iter().filter(|(_, q)| q.price.is_some()).collect();
+
+  if quotes.is_empty() {
+    return Ok(());
+  }
+
+  let bucket_start: DateTime<FixedOffset> =
+    Utc::now().duration_trunc(Duration::minutes(5))?.into();
+  let optional_float =
+    |value: Option<f64>| value.map(PrismaValue::Float).unwrap_or(PrismaValue::Null);
+
+  let mut params = vec![];
+  let values = quotes
+    .iter()
+    .map(|(business_id, quote)| {
+      let price = quote.price.unwrap_or_default();
+      params.extend([
+        PrismaValue::Int(*business_id as i64),
+        PrismaValue::DateTime(bucket_start),
+        PrismaValue::Float(price),
+        PrismaValue::Float(price),
+        PrismaValue::Float(price),
+        PrismaValue::Float(price),
+        optional_float(quote.volume_24h),
+        optional_float(quote.market_cap),
+      ]);
+      r#"({}, CAST('five_minutes' AS "SnapshotTier"), {}, {}, {}, {}, {}, {}, {})"#
+    })
+    .collect::<Vec<&str>>()
+    .join(", ");
+
+  prisma_client
+    ._execute_raw(Raw::new(
+      &format!(
+        r#"
+        INSERT INTO "quote_snapshot"
+          ("business_id", "tier", "bucket_start", "open", "high", "low", "close", "volume_24h", "market_cap")
+        VALUES {values}
+        ON CONFLICT ("business_id", "tier", "bucket_start") DO UPDATE SET
+          "high" = GREATEST("quote_snapshot"."high", EXCLUDED."high"),
+          "low" = LEAST("quote_snapshot"."low", EXCLUDED."low"),
+          "close" = EXCLUDED."close",
+          "volume_24h" = EXCLUDED."volume_24h",
+          "market_cap" = EXCLUDED."market_cap"
+        "#
+      ),
+      params,
+    ))
+    .exec()
+    .observe("execute_raw.record_snapshots")
+    .await?;
+
+  Ok(())
+}
+
+// Rebuilds the coarser bucket that contains every finer bucket since `since`.
+#[tracing::instrument(skip(prisma_client))]
+async fn rollup(
+  prisma_client: &PrismaClient,
+  from_tier: &str,
+  to_tier: &str,
+  trunc_unit: &str,
+  since: DateTime<Utc>,
+) -> Result<()> {
+  let since: DateTime<FixedOffset> = since.into();
+
+  prisma_client
+    ._execute_raw(Raw::new(
+      &format!(
+        r#"
+        INSERT INTO "quote_snapshot"
+          ("business_id", "tier", "bucket_start", "open", "high", "low", "close", "volume_24h", "market_cap")
+        SELECT
+          "business_id",
+          CAST('{to_tier}' AS "SnapshotTier"),
+          date_trunc('{trunc_unit}', "bucket_start"),
+          (array_agg("open" ORDER BY "bucket_start"))[1],
+          MAX("high"),
+          MIN("low"),
+          (array_agg("close" ORDER BY "bucket_start" DESC))[1],
+          (array_agg("volume_24h" ORDER BY "bucket_start" DESC))[1],
+          (array_agg("market_cap" ORDER BY "bucket_start" DESC))[1]
+        FROM "quote_snapshot"
+        WHERE "tier" = '{from_tier}'
+        AND "bucket_start" >= date_trunc('{trunc_unit}', CAST({{}} AS TIMESTAMP))
+        GROUP BY "business_id", date_trunc('{trunc_unit}', "bucket_start")
+        ON CONFLICT ("business_id", "tier", "bucket_start") DO UPDATE SET
+          "open" = EXCLUDED."open",
+          "high" = EXCLUDED."high",
+          "low" = EXCLUDED."low",
+          "close" = EXCLUDED."close",
+          "volume_24h" = EXCLUDED."volume_24h",
+          "market_cap" = EXCLUDED."market_cap"
+        "#
+      ),
+      vec![PrismaValue::DateTime(since)],
+    ))
+    .exec()
+    .observe("execute_raw.rollup")
+    .await?;
+
+  Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn downsample_snapshots(prisma_client: &PrismaClient) -> Result<()> {
+  let now = Utc::now();
+
+  rollup(
+    prisma_client,
+    "five_minutes",
+    "one_hour",
+    "hour",
+    now - Duration::hours(2),
+  )
+  .await?;
+  rollup(
+    prisma_client,
+    "one_hour",
+    "one_day",
+    "day",
+    now - Duration::days(2),
+  )
+  .await?;
+
+  let five_minutes_expiry: DateTime<FixedOffset> =
+    (now - Duration::days(FIVE_MINUTES_RETENTION_DAYS)).into();
+  let one_hour_expiry: DateTime<FixedOffset> =
+    (now - Duration::days(ONE_HOUR_RETENTION_DAYS)).into();
+
+  prisma_client
+    ._execute_raw(raw!(
+      r#"
+      DELETE FROM "quote_snapshot"
+      WHERE ("tier" = 'five_minutes' AND "bucket_start" < {})
+      OR ("tier" = 'one_hour' AND "bucket_start" < {})
+      "#,
+      PrismaValue::DateTime(five_minutes_expiry),
+      PrismaValue::DateTime(one_hour_expiry)
+    ))
+    .exec()
+    .observe("execute_raw.downsample_snapshots")
+    .await?;
+
+  Ok(())
+}
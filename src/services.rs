@@ -1,3 +1,11 @@
 pub mod auth;
+pub mod banner;
 pub mod business;
+pub mod campaign;
+pub mod did;
+pub mod health;
+pub mod job;
+pub mod point;
+pub mod social;
 pub mod user;
+pub mod voucher;
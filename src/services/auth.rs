@@ -1,58 +1,157 @@
+use crate::config::AuthConfig;
+use crate::database::observe::ObserveQuery;
 use crate::database::prisma;
+use crate::database::redis_conn::RedisConn;
 use crate::intercept::sercurity::Claims;
 use crate::{utils, AppState};
 use anyhow::Result;
-use axum::{extract::State, Json};
-use error::AuthError;
+use axum::{
+  extract::State,
+  response::{IntoResponse, Response},
+  Json,
+};
+use error::{AppError, AuthError};
 use ethers::types::Signature;
 use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use prisma_client_rust::QueryError;
 use serde::{Deserialize, Serialize};
 use siwe::Message;
-use std::env;
 use std::sync::Arc;
+use utoipa::ToSchema;
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Tokens {
   access_token: String,
   refresh_token: String,
+  #[schema(value_type = crate::open_api::schemas::UserClaims)]
   user: user_claims::Data,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct AuthPayload {
   message: String,
   signature: String,
 }
 
+const LOGIN_NONCE_TTL_SECONDS: u64 = 5 * 60;
+
+// Keyed by the nonce itself, whoever logs in is not known before the message is signed.
+fn login_nonce_key(nonce: &str) -> String {
+  format!("login_nonce:{nonce}")
+}
+
 #[axum_macros::debug_handler]
-pub async fn get_nonce() -> String {
-  siwe::generate_nonce()
+#[utoipa::path(
+  get,
+  path = "/auth/nonce",
+  tag = "auth",
+  responses(
+      (status = 200, description = "return a single use nonce to put in the SIWE message, valid for 5 minutes", body = String, content_type = "text/plain")
+  )
+)]
+pub async fn get_nonce(State(state): State<AppState>) -> Result<String, AppError> {
+  let mut redis_conn = state.redis_conn;
+  let nonce = siwe::generate_nonce();
+
+  redis::cmd("SET")
+    .arg(login_nonce_key(&nonce))
+    .arg(1)
+    .arg("EX")
+    .arg(LOGIN_NONCE_TTL_SECONDS)
+    .query_async::<_, ()>(&mut redis_conn)
+    .await?;
+
+  Ok(nonce)
 }
 
 #[axum_macros::debug_handler]
+#[utoipa::path(
+  post,
+  path = "/auth/login",
+  tag = "auth",
+  request_body = AuthPayload,
+  responses(
+      (status = 200, description = "return access and refresh tokens for the wallet that signed the SIWE message", body = Tokens),
+      (status = 400, description = "nonce is unknown or used, or the message is for another domain or expired", body = crate::open_api::schemas::ErrorBody),
+      (status = 401, description = "signature is invalid", body = crate::open_api::schemas::ErrorBody)
+  )
+)]
 pub async fn login(
   State(state): State<AppState>,
   Json(payload): Json<AuthPayload>,
-) -> Result<Json<Tokens>, AuthError> {
+) -> Result<Json<Tokens>, Response> {
   let AppState {
     mut redis_conn,
     prisma_client,
+    config,
+    ..
   } = state;
   let AuthPayload { signature, message } = payload;
 
+  let siwe_message = message
+    .parse::<Message>()
+    .map_err(|_| AuthError::WrongSignature.into_response())?;
+
+  // Taken before anything is checked, a nonce is spent by any attempt.
+  let issued = redis::cmd("GETDEL")
+    .arg(login_nonce_key(&siwe_message.nonce))
+    .query_async::<_, Option<String>>(&mut redis_conn)
+    .await
+    .map_err(|err| AppError::from(err).into_response())?;
+  let nonce = issued.map(|_| siwe_message.nonce.as_str());
+
+  check_siwe_message(&siwe_message, nonce, &config.auth.siwe_domain)
+    .map_err(IntoResponse::into_response)?;
+
+  let wallet_address = verify_siwe(&message, &signature).map_err(IntoResponse::into_response)?;
+  let user_claims = handle_address(wallet_address, prisma_client)
+    .await
+    .map_err(|err| AppError::from(err).into_response())?;
+  let tokens = generate_tokens(user_claims, &mut redis_conn, &config.auth)
+    .await
+    .map_err(|err| AppError::from(err).into_response())?;
+  metrics::increment_counter!("auth_outcomes_total", "outcome" => "logged_in");
+  Ok(Json(tokens))
+}
+
+// A message is accepted once, for the domain it was issued for and before it expires. `nonce` is the
+// issued nonce that was just taken, if any.
+pub fn check_siwe_message(
+  message: &Message,
+  nonce: Option<&str>,
+  domain: &str,
+) -> Result<(), AppError> {
+  if nonce != Some(message.nonce.as_str()) {
+    return Err(AppError::bad_request(
+      "Nonce is unknown or was already used, request a new one",
+    ));
+  }
+
+  if !message.domain.as_str().eq_ignore_ascii_case(domain) {
+    return Err(AppError::bad_request(
+      "Message was signed for another domain",
+    ));
+  }
+
+  if message.expiration_time.is_none() || !message.valid_now() {
+    return Err(AppError::bad_request(
+      "Message has no expiration time or has expired",
+    ));
+  }
+
+  Ok(())
+}
+
+// Returns the checksummed address that signed the SIWE message.
+pub fn verify_siwe(message: &str, signature: &str) -> Result<String, AuthError> {
   match message.parse::<Message>() {
     Ok(siwe_message) => {
-      if let Ok(signature) = signature.as_str().parse::<Signature>() {
+      if let Ok(signature) = signature.parse::<Signature>() {
         match signature.verify(message, siwe_message.address) {
-          Ok(_) => {
-            let wallet_address = siwe::eip55(&siwe_message.address);
-            let user_claims = handle_address(wallet_address, prisma_client).await;
-            let tokens = generate_tokens(user_claims, &mut redis_conn).await.unwrap();
-            Ok(Json(tokens))
-          }
+          Ok(_) => Ok(siwe::eip55(&siwe_message.address)),
           Err(err) => {
-            dbg!(err);
+            tracing::debug!(error = %err, "siwe signature does not match");
             Err(AuthError::WrongSignature)
           }
         }
@@ -72,10 +171,11 @@ prisma::user::select!( user_claims {
   is_admin
 });
 
-async fn handle_address(
+#[tracing::instrument(skip(prisma_client))]
+pub async fn handle_address(
   wallet_address: String,
   prisma_client: Arc<prisma::PrismaClient>,
-) -> user_claims::Data {
+) -> Result<user_claims::Data, QueryError> {
   let user = prisma_client
     .user()
     .find_unique(prisma::user::wallet_address::equals(
@@ -83,18 +183,18 @@ async fn handle_address(
     ))
     .select(user_claims::select())
     .exec()
-    .await
-    .unwrap();
+    .observe("user.find_unique")
+    .await?;
 
   match user {
-    Some(user) => user,
+    Some(user) => Ok(user),
     None => {
       let new_user = prisma_client
         .user()
         .create(wallet_address.to_owned(), vec![])
         .exec()
-        .await
-        .unwrap();
+        .observe("user.create")
+        .await?;
 
       prisma_client
         .social()
@@ -103,39 +203,44 @@ async fn handle_address(
           vec![],
         )
         .exec()
-        .await
-        .unwrap();
+        .observe("social.create")
+        .await?;
 
-      user_claims::Data {
+      Ok(user_claims::Data {
         id: new_user.id,
         wallet_address,
         is_admin: false,
-      }
+      })
     }
   }
 }
 
+#[tracing::instrument(skip_all, fields(user_id = user_claims.id))]
 async fn generate_tokens(
   user_claims: user_claims::Data,
-  redis_conn: &mut redis::aio::ConnectionManager,
+  redis_conn: &mut RedisConn,
+  auth: &AuthConfig,
 ) -> Result<Tokens> {
-  let secret = env::var("JWT_SECRET")?;
-  let refresh_secret = env::var("JWT_REFRESH_SECRET")?;
-
   let header = Header::new(Algorithm::HS256);
 
-  let secret_key = EncodingKey::from_secret(secret.as_bytes());
-  let refresh_key = EncodingKey::from_secret(refresh_secret.as_bytes());
+  let secret_key = EncodingKey::from_secret(auth.jwt_secret.as_bytes());
+  let refresh_key = EncodingKey::from_secret(auth.jwt_refresh_secret.as_bytes());
 
-  let access_token = encode(&header, &Claims::new_access(&user_claims), &secret_key)?;
-  let refresh_token = encode(&header, &Claims::new_refresh(&user_claims), &refresh_key)?;
+  let access_token = encode(
+    &header,
+    &Claims::new_access(&user_claims, auth.access_token_ttl),
+    &secret_key,
+  )?;
+  let refresh_token = encode(
+    &header,
+    &Claims::new_refresh(&user_claims, auth.refresh_token_ttl),
+    &refresh_key,
+  )?;
 
-  redis_conn
-    .send_packed_command(
-      redis::cmd("SET")
-        .arg(utils::refresh_token_generate((&user_claims).id))
-        .arg(&refresh_token),
-    )
+  redis::cmd("SET")
+    .arg(utils::refresh_token_generate((&user_claims).id))
+    .arg(&refresh_token)
+    .query_async::<_, ()>(redis_conn)
     .await?;
 
   Ok(Tokens {
@@ -144,3 +249,39 @@ async fn generate_tokens(
     user: user_claims,
   })
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn login_message(domain: &str, expiration_time: &str) -> Message {
+    format!(
+      "{domain} wants you to sign in with your Ethereum account:\n\
+       0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2\n\
+       \n\
+       \n\
+       URI: https://{domain}\n\
+       Version: 1\n\
+       Chain ID: 1\n\
+       Nonce: k3mR7qLx2wZp\n\
+       Issued At: 2023-06-01T00:00:00Z\n\
+       Expiration Time: {expiration_time}"
+    )
+    .parse()
+    .unwrap()
+  }
+
+  #[test]
+  fn login_message_needs_an_issued_nonce_the_domain_and_an_expiry() {
+    let domain = "localhost:3000";
+    let valid = login_message(domain, "2100-01-01T00:00:00Z");
+
+    assert!(check_siwe_message(&valid, Some("k3mR7qLx2wZp"), domain).is_ok());
+    // Replayed, the nonce was taken by the first login.
+    assert!(check_siwe_message(&valid, None, domain).is_err());
+    assert!(check_siwe_message(&valid, Some("k3mR7qLx2wZp"), "app.example.com").is_err());
+
+    let expired = login_message(domain, "2023-06-01T00:05:00Z");
+    assert!(check_siwe_message(&expired, Some("k3mR7qLx2wZp"), domain).is_err());
+  }
+}
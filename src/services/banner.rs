@@ -0,0 +1,529 @@
+use crate::database::cache;
+use crate::database::observe::ObserveQuery;
+use crate::database::prisma::{self, banner, storage, PrismaClient};
+use crate::database::redis_conn::RedisConn;
+use crate::{
+  intercept::{sercurity::AdminGuard, validate::ValidatedJson},
+  storage::image_extension,
+  utils::random_string,
+  AppState,
+};
+use anyhow::Result;
+use axum::{
+  extract::{Multipart, Path, State},
+  Json,
+};
+use chrono::{DateTime, FixedOffset, Utc};
+use error::AppError;
+use prisma_client_rust::Direction;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use utoipa::ToSchema;
+use validator::Validate;
+
+const BANNER_CACHE_TAG: &str = "banners";
+const ACTIVE_BANNERS_KEY: &str = "banners:active";
+const ACTIVE_BANNERS_MAX_TTL_SECONDS: i64 = 60 * 60;
+
+prisma::banner::select!(banner_detail {
+  id
+  created_at
+  expried_time
+  archived
+  storages: select {
+    id
+    url
+    tag
+  }
+});
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveBanner {
+  id: i32,
+  url: String,
+  tag: Option<String>,
+  #[schema(value_type = String, format = DateTime)]
+  expires_at: DateTime<FixedOffset>,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct CreateBannerPayload {
+  source_id: i32,
+
+  #[schema(value_type = String, format = DateTime)]
+  expried_time: DateTime<FixedOffset>,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct UpdateBannerPayload {
+  source_id: Option<i32>,
+
+  #[schema(value_type = Option<String>, format = DateTime)]
+  expried_time: Option<DateTime<FixedOffset>>,
+
+  archived: Option<bool>,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct UpdateStoragePayload {
+  #[validate(length(max = 64))]
+  tag: Option<String>,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn invalidate_banner_cache(redis_conn: &mut RedisConn) {
+  cache::invalidate_tag(redis_conn, BANNER_CACHE_TAG).await;
+}
+
+#[tracing::instrument(skip_all)]
+async fn active_banners(prisma_client: &PrismaClient) -> Result<Vec<ActiveBanner>> {
+  let banners = prisma_client
+    .banner()
+    .find_many(vec![
+      banner::archived::equals(false),
+      banner::expried_time::gt(Utc::now().into()),
+    ])
+    .order_by(banner::created_at::order(Direction::Desc))
+    .select(banner_detail::select())
+    .exec()
+    .observe("banner.find_many")
+    .await?;
+
+  Ok(
+    banners
+      .into_iter()
+      .map(|banner| ActiveBanner {
+        id: banner.id,
+        url: banner.storages.url,
+        tag: banner.storages.tag,
+        expires_at: banner.expried_time,
+      })
+      .collect(),
+  )
+}
+
+// The cached list is only valid until the first of its banners expires.
+fn active_banners_ttl(banners: &[ActiveBanner]) -> Duration {
+  let now = Utc::now();
+
+  let seconds = banners
+    .iter()
+    .map(|banner| (banner.expires_at.with_timezone(&Utc) - now).num_seconds())
+    .min()
+    .unwrap_or(ACTIVE_BANNERS_MAX_TTL_SECONDS)
+    .clamp(1, ACTIVE_BANNERS_MAX_TTL_SECONDS);
+
+  Duration::from_secs(seconds as u64)
+}
+
+async fn ensure_storage_exists(
+  prisma_client: &PrismaClient,
+  storage_id: i32,
+) -> Result<(), AppError> {
+  prisma_client
+    .storage()
+    .find_unique(storage::id::equals(storage_id))
+    .exec()
+    .observe("storage.find_unique")
+    .await?
+    .map(|_| ())
+    .ok_or_else(|| AppError::not_found("Storage asset not found"))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  get,
+  path = "/banners",
+  tag = "banner",
+  responses(
+      (status = 200, description = "return banners that are not expired yet", body = [ActiveBanner])
+  )
+)]
+pub async fn get_banners(
+  State(state): State<AppState>,
+) -> Result<Json<Vec<ActiveBanner>>, AppError> {
+  let AppState {
+    prisma_client,
+    mut redis_conn,
+    ..
+  } = state;
+
+  let banners = cache::get_or_load(
+    &mut redis_conn,
+    ACTIVE_BANNERS_KEY,
+    &[BANNER_CACHE_TAG],
+    |banners| active_banners_ttl(banners),
+    || active_banners(&prisma_client),
+  )
+  .await?;
+
+  Ok(Json(banners))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  get,
+  path = "/admin/banners",
+  tag = "banner",
+  responses(
+      (status = 200, description = "return all banners including expired and archived ones")
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn get_all_banners(
+  AdminGuard(_claims): AdminGuard,
+  State(state): State<AppState>,
+) -> Result<Json<Vec<banner_detail::Data>>, AppError> {
+  let banners = state
+    .prisma_client
+    .banner()
+    .find_many(vec![])
+    .order_by(banner::created_at::order(Direction::Desc))
+    .select(banner_detail::select())
+    .exec()
+    .observe("banner.find_many")
+    .await?;
+
+  Ok(Json(banners))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  post,
+  path = "/admin/banners",
+  tag = "banner",
+  request_body = CreateBannerPayload,
+  responses(
+      (status = 200, description = "return the created banner"),
+      (status = 404, description = "storage asset does not exist", body = crate::open_api::schemas::ErrorBody)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn create_banner(
+  AdminGuard(_claims): AdminGuard,
+  State(state): State<AppState>,
+  ValidatedJson(payload): ValidatedJson<CreateBannerPayload>,
+) -> Result<Json<banner_detail::Data>, AppError> {
+  let AppState {
+    prisma_client,
+    mut redis_conn,
+    ..
+  } = state;
+  let CreateBannerPayload {
+    source_id,
+    expried_time,
+  } = payload;
+
+  ensure_storage_exists(&prisma_client, source_id).await?;
+
+  let banner = prisma_client
+    .banner()
+    .create(expried_time, storage::id::equals(source_id), vec![])
+    .select(banner_detail::select())
+    .exec()
+    .observe("banner.create")
+    .await?;
+
+  invalidate_banner_cache(&mut redis_conn).await;
+
+  Ok(Json(banner))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  patch,
+  path = "/admin/banners/{id}",
+  tag = "banner",
+  params(
+    ("id" = i32, Path, description = "banner id")
+  ),
+  request_body = UpdateBannerPayload,
+  responses(
+      (status = 200, description = "return the updated banner"),
+      (status = 404, description = "banner or storage asset does not exist", body = crate::open_api::schemas::ErrorBody)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn update_banner(
+  AdminGuard(_claims): AdminGuard,
+  Path(banner_id): Path<i32>,
+  State(state): State<AppState>,
+  ValidatedJson(payload): ValidatedJson<UpdateBannerPayload>,
+) -> Result<Json<banner_detail::Data>, AppError> {
+  let AppState {
+    prisma_client,
+    mut redis_conn,
+    ..
+  } = state;
+  let UpdateBannerPayload {
+    source_id,
+    expried_time,
+    archived,
+  } = payload;
+
+  let exists = prisma_client
+    .banner()
+    .find_unique(banner::id::equals(banner_id))
+    .exec()
+    .observe("banner.find_unique")
+    .await?
+    .is_some();
+
+  if !exists {
+    return Err(AppError::not_found("Banner not found"));
+  }
+
+  let mut params = vec![];
+
+  if let Some(source_id) = source_id {
+    ensure_storage_exists(&prisma_client, source_id).await?;
+    params.push(banner::storages::connect(storage::id::equals(source_id)));
+  }
+
+  if let Some(expried_time) = expried_time {
+    params.push(banner::expried_time::set(expried_time));
+  }
+
+  if let Some(archived) = archived {
+    params.push(banner::archived::set(archived));
+  }
+
+  let banner = prisma_client
+    .banner()
+    .update(banner::id::equals(banner_id), params)
+    .select(banner_detail::select())
+    .exec()
+    .observe("banner.update")
+    .await?;
+
+  invalidate_banner_cache(&mut redis_conn).await;
+
+  Ok(Json(banner))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  delete,
+  path = "/admin/banners/{id}",
+  tag = "banner",
+  params(
+    ("id" = i32, Path, description = "banner id")
+  ),
+  responses(
+      (status = 200, description = "banner was deleted"),
+      (status = 404, description = "banner does not exist", body = crate::open_api::schemas::ErrorBody)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn delete_banner(
+  AdminGuard(_claims): AdminGuard,
+  Path(banner_id): Path<i32>,
+  State(state): State<AppState>,
+) -> Result<(), AppError> {
+  let AppState {
+    prisma_client,
+    mut redis_conn,
+    ..
+  } = state;
+
+  let deleted = prisma_client
+    .banner()
+    .delete_many(vec![banner::id::equals(banner_id)])
+    .exec()
+    .observe("banner.delete_many")
+    .await?;
+
+  if deleted == 0 {
+    return Err(AppError::not_found("Banner not found"));
+  }
+
+  invalidate_banner_cache(&mut redis_conn).await;
+
+  Ok(())
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  get,
+  path = "/admin/storages",
+  tag = "banner",
+  responses(
+      (status = 200, description = "return all uploaded storage assets")
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn get_storages(
+  AdminGuard(_claims): AdminGuard,
+  State(state): State<AppState>,
+) -> Result<Json<Vec<storage::Data>>, AppError> {
+  let storages = state
+    .prisma_client
+    .storage()
+    .find_many(vec![])
+    .order_by(storage::created_at::order(Direction::Desc))
+    .exec()
+    .observe("storage.find_many")
+    .await?;
+
+  Ok(Json(storages))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  post,
+  path = "/admin/storages",
+  tag = "banner",
+  request_body(content = Vec<u8>, content_type = "multipart/form-data", description = "image in the `file` field and an optional `tag` field"),
+  responses(
+      (status = 200, description = "return the uploaded storage asset"),
+      (status = 400, description = "file is missing or is not an image", body = crate::open_api::schemas::ErrorBody)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn upload_storage(
+  AdminGuard(_claims): AdminGuard,
+  State(state): State<AppState>,
+  mut multipart: Multipart,
+) -> Result<Json<storage::Data>, AppError> {
+  let mut file = None;
+  let mut tag = None;
+
+  while let Some(field) = multipart.next_field().await? {
+    match field.name() {
+      Some("file") => {
+        let content_type = field.content_type().unwrap_or_default().to_string();
+        file = Some((content_type, field.bytes().await?));
+      }
+      Some("tag") => tag = Some(field.text().await?),
+      _ => {}
+    }
+  }
+
+  let (content_type, bytes) = file.ok_or_else(|| AppError::bad_request("Missing file field"))?;
+  let extension = image_extension(&content_type)
+    .ok_or_else(|| AppError::bad_request("Only png, jpeg, webp or gif images are allowed"))?;
+
+  let key = format!("banners/{}.{extension}", random_string(16));
+  let url = state
+    .storage
+    .put(&key, &content_type, bytes.to_vec())
+    .await?;
+
+  let storage = state
+    .prisma_client
+    .storage()
+    .create(url, vec![storage::tag::set(tag)])
+    .exec()
+    .observe("storage.create")
+    .await?;
+
+  Ok(Json(storage))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  patch,
+  path = "/admin/storages/{id}",
+  tag = "banner",
+  params(
+    ("id" = i32, Path, description = "storage asset id")
+  ),
+  request_body = UpdateStoragePayload,
+  responses(
+      (status = 200, description = "return the updated storage asset"),
+      (status = 404, description = "storage asset does not exist", body = crate::open_api::schemas::ErrorBody)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn update_storage(
+  AdminGuard(_claims): AdminGuard,
+  Path(storage_id): Path<i32>,
+  State(state): State<AppState>,
+  ValidatedJson(payload): ValidatedJson<UpdateStoragePayload>,
+) -> Result<Json<storage::Data>, AppError> {
+  let AppState {
+    prisma_client,
+    mut redis_conn,
+    ..
+  } = state;
+
+  ensure_storage_exists(&prisma_client, storage_id).await?;
+
+  let storage = prisma_client
+    .storage()
+    .update(
+      storage::id::equals(storage_id),
+      vec![storage::tag::set(payload.tag)],
+    )
+    .exec()
+    .observe("storage.update")
+    .await?;
+
+  invalidate_banner_cache(&mut redis_conn).await;
+
+  Ok(Json(storage))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  delete,
+  path = "/admin/storages/{id}",
+  tag = "banner",
+  params(
+    ("id" = i32, Path, description = "storage asset id")
+  ),
+  responses(
+      (status = 200, description = "storage asset was deleted, the uploaded file is kept"),
+      (status = 404, description = "storage asset does not exist", body = crate::open_api::schemas::ErrorBody),
+      (status = 409, description = "storage asset is still used by a banner", body = crate::open_api::schemas::ErrorBody)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn delete_storage(
+  AdminGuard(_claims): AdminGuard,
+  Path(storage_id): Path<i32>,
+  State(state): State<AppState>,
+) -> Result<(), AppError> {
+  let prisma_client = state.prisma_client;
+
+  ensure_storage_exists(&prisma_client, storage_id).await?;
+
+  let banners = prisma_client
+    .banner()
+    .count(vec![banner::source_id::equals(storage_id)])
+    .exec()
+    .observe("banner.count")
+    .await?;
+
+  if banners > 0 {
+    return Err(AppError::conflict(
+      "Storage asset is still used by a banner",
+    ));
+  }
+
+  prisma_client
+    .storage()
+    .delete(storage::id::equals(storage_id))
+    .exec()
+    .observe("storage.delete")
+    .await?;
+
+  Ok(())
+}
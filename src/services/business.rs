@@ -1,17 +1,31 @@
-use crate::database::prisma;
+use crate::database::cache;
+use crate::database::observe::ObserveQuery;
+use crate::database::prisma::{self, BusinessStatus, PrismaClient};
+use crate::database::redis_conn::RedisConn;
 use crate::{
   database::query_buider::QueryBuider,
-  intercept::{did::Did, validate::ValidatedQuery},
+  intercept::{
+    did::Did,
+    sercurity::AdminGuard,
+    validate::{ValidatedJson, ValidatedQuery},
+  },
+  market_data::Quote,
+  schedulers::cmc::latest_quotes,
   AppState,
 };
-use axum::extract::State;
+use axum::extract::{Path, State};
 use axum::Json;
+use chrono::{DateTime, Duration, FixedOffset, Utc};
 use error::AppError;
-use futures::future;
-use prisma_client_rust::raw;
-use serde::Deserialize;
-use utoipa::IntoParams;
-use validator::Validate;
+use prisma_client_rust::{raw, Direction, QueryError};
+use serde::{Deserialize, Serialize};
+use std::time::Duration as StdDuration;
+use utoipa::{IntoParams, ToSchema};
+use validator::{Validate, ValidationError};
+
+const BUSINESS_CACHE_TAG: &str = "businesses";
+// Short, every request within it gets the same sample.
+const BUSINESS_SAMPLE_TTL: StdDuration = StdDuration::from_secs(30);
 
 prisma::business::select!(rand_business {
   id
@@ -27,47 +41,71 @@ prisma::business::select!(rand_business {
   }
 });
 
+#[derive(Serialize)]
+pub struct BusinessWithQuote {
+  #[serde(flatten)]
+  business: rand_business::Data,
+  quote: Option<Quote>,
+}
+
 #[derive(Deserialize, IntoParams, Validate)]
 #[into_params(parameter_in = Query)]
 pub struct RandomBusinessesQuery {
-  #[validate(range(min = 1))]
+  #[validate(range(min = 1, max = 50))]
   limit: u32,
 
+  #[validate(custom = "validate_filter")]
   r#type: Option<String>,
 
+  #[validate(custom = "validate_filter")]
   main_category: Option<String>,
 
   banner_only: Option<bool>,
 }
 
-#[axum_macros::debug_handler]
-#[utoipa::path(
-  get,
-  params(
-    RandomBusinessesQuery
-  ),
-  path = "/businesses",
-  tag = "business",
-  responses(
-      (status = 200, description = "return list businesses")
-  ),
-  security(
-    ("BearerAuth" = []),
-  )
-)]
-pub async fn get_businesses(
-  ValidatedQuery(query): ValidatedQuery<RandomBusinessesQuery>,
-  _did: Option<Did>,
-  State(state): State<AppState>,
-) -> Result<Json<Vec<Option<rand_business::Data>>>, AppError> {
-  let prisma_client = state.prisma_client;
-  let RandomBusinessesQuery {
-    limit,
-    r#type,
-    main_category,
-    banner_only,
-  } = query;
+// Types and categories are names such as `DeFi` or `Layer 2`, anything else cannot match a business.
+fn validate_filter(value: &str) -> Result<(), ValidationError> {
+  let is_name = (1..=64).contains(&value.len())
+    && value
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || matches!(c, ' ' | '-' | '_' | '&' | '.'));
+
+  if is_name {
+    Ok(())
+  } else {
+    Err(ValidationError::new("invalid_filter"))
+  }
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct UpdateBusinessPayload {
+  #[schema(value_type = Option<String>, example = "approved")]
+  status: Option<BusinessStatus>,
+
+  #[validate(length(min = 1, max = 2000))]
+  overview: Option<String>,
+
+  #[validate(url)]
+  logo: Option<String>,
 
+  #[validate(custom = "validate_filter")]
+  main_category: Option<String>,
+}
+
+// Must be called whenever a business is approved, edited or withdrawn.
+pub async fn invalidate_business_cache(redis_conn: &mut RedisConn) {
+  cache::invalidate_tag(redis_conn, BUSINESS_CACHE_TAG).await;
+}
+
+// At most `limit` approved businesses matching the filters, picked at random by Postgres.
+#[tracing::instrument(skip(prisma_client))]
+async fn random_businesses(
+  prisma_client: &PrismaClient,
+  b_type: Option<&str>,
+  main_category: Option<&str>,
+  banner_only: bool,
+  limit: u32,
+) -> Result<Vec<rand_business::Data>, QueryError> {
   #[derive(Deserialize)]
   struct BusinessId {
     id: i32,
@@ -76,7 +114,7 @@ pub async fn get_businesses(
   let mut query_builder = QueryBuider::new();
   query_builder.r#where(r#" "b"."status" = 'approved' "#);
 
-  if let Some(b_type) = r#type {
+  if let Some(b_type) = b_type {
     query_builder.and_where(format!(r#" '{b_type}' = ANY("b"."types") "#))
   }
 
@@ -84,7 +122,7 @@ pub async fn get_businesses(
     query_builder.and_where(format!(r#" "b"."main_category" = '{main_category}' "#))
   }
 
-  if banner_only.unwrap_or_default() {
+  if banner_only {
     query_builder.and_where(
       r#"
       (
@@ -96,7 +134,7 @@ pub async fn get_businesses(
     )
   }
 
-  let data = prisma_client
+  let ids = prisma_client
     ._query_raw::<BusinessId>(raw!(format!(
       r#"
       SELECT
@@ -110,18 +148,247 @@ pub async fn get_businesses(
     )
     .as_str()))
     .exec()
+    .observe("query_raw.random_businesses")
+    .await?;
+
+  prisma_client
+    .business()
+    .find_many(vec![prisma::business::id::in_vec(
+      ids.into_iter().map(|b| b.id).collect(),
+    )])
+    .select(rand_business::select())
+    .exec()
+    .observe("business.find_many")
+    .await
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  get,
+  params(
+    RandomBusinessesQuery
+  ),
+  path = "/businesses",
+  tag = "business",
+  responses(
+      (status = 200, description = "return list businesses", body = [crate::open_api::schemas::BusinessWithQuote])
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn get_businesses(
+  ValidatedQuery(query): ValidatedQuery<RandomBusinessesQuery>,
+  _did: Option<Did>,
+  State(state): State<AppState>,
+) -> Result<Json<Vec<BusinessWithQuote>>, AppError> {
+  let AppState {
+    prisma_client,
+    mut redis_conn,
+    ..
+  } = state;
+  let RandomBusinessesQuery {
+    limit,
+    r#type,
+    main_category,
+    banner_only,
+  } = query;
+
+  let banner_only = banner_only.unwrap_or_default();
+
+  let businesses = cache::get_or_load(
+    &mut redis_conn,
+    &cache::hashed_key(
+      "businesses:sample",
+      &(
+        r#type.as_deref(),
+        main_category.as_deref(),
+        banner_only,
+        limit,
+      ),
+    ),
+    &[BUSINESS_CACHE_TAG],
+    |_| BUSINESS_SAMPLE_TTL,
+    || {
+      random_businesses(
+        &prisma_client,
+        r#type.as_deref(),
+        main_category.as_deref(),
+        banner_only,
+        limit,
+      )
+    },
+  )
+  .await?;
+
+  // Quotes are not part of the sample, they change every crawl.
+  let cmc_ids: Vec<i32> = businesses.iter().filter_map(|b| b.cmc_id).collect();
+  let mut quotes = latest_quotes(&mut redis_conn, &cmc_ids).await?;
+
+  Ok(Json(
+    businesses
+      .into_iter()
+      .map(|business| BusinessWithQuote {
+        quote: business.cmc_id.and_then(|cmc_id| quotes.remove(&cmc_id)),
+        business,
+      })
+      .collect(),
+  ))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  patch,
+  path = "/admin/businesses/{id}",
+  tag = "business",
+  params(
+    ("id" = i32, Path, description = "business id")
+  ),
+  request_body = UpdateBusinessPayload,
+  responses(
+      (status = 200, description = "return the updated business", body = crate::open_api::schemas::RandBusiness),
+      (status = 404, description = "business does not exist", body = crate::open_api::schemas::ErrorBody)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn update_business(
+  AdminGuard(_claims): AdminGuard,
+  Path(business_id): Path<i32>,
+  State(state): State<AppState>,
+  ValidatedJson(payload): ValidatedJson<UpdateBusinessPayload>,
+) -> Result<Json<rand_business::Data>, AppError> {
+  let AppState {
+    prisma_client,
+    mut redis_conn,
+    ..
+  } = state;
+  let UpdateBusinessPayload {
+    status,
+    overview,
+    logo,
+    main_category,
+  } = payload;
+
+  prisma_client
+    .business()
+    .find_unique(prisma::business::id::equals(business_id))
+    .select(prisma::business::select!({ id }))
+    .exec()
+    .observe("business.find_unique")
+    .await?
+    .ok_or_else(|| AppError::not_found("Business not found"))?;
+
+  let mut params = vec![];
+
+  if let Some(status) = status {
+    params.push(prisma::business::status::set(status));
+  }
+
+  if let Some(overview) = overview {
+    params.push(prisma::business::overview::set(overview));
+  }
+
+  if let Some(logo) = logo {
+    params.push(prisma::business::logo::set(Some(logo)));
+  }
+
+  if let Some(main_category) = main_category {
+    params.push(prisma::business::main_category::set(main_category));
+  }
+
+  let business = prisma_client
+    .business()
+    .update(prisma::business::id::equals(business_id), params)
+    .select(rand_business::select())
+    .exec()
+    .observe("business.update")
+    .await?;
+
+  invalidate_business_cache(&mut redis_conn).await;
+
+  Ok(Json(business))
+}
+
+prisma::quote_snapshot::select!(candle {
+  bucket_start
+  open
+  high
+  low
+  close
+  volume_24h
+  market_cap
+});
+
+#[derive(Deserialize, ToSchema, Clone, Copy)]
+pub enum ChartRange {
+  #[serde(rename = "24h")]
+  Day,
+  #[serde(rename = "7d")]
+  Week,
+  #[serde(rename = "30d")]
+  Month,
+}
+
+#[derive(Deserialize, IntoParams, Validate)]
+#[into_params(parameter_in = Query)]
+pub struct ChartQuery {
+  range: ChartRange,
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  get,
+  params(
+    ("id" = i32, Path, description = "business id"),
+    ChartQuery
+  ),
+  path = "/businesses/{id}/chart",
+  tag = "business",
+  responses(
+      (status = 200, description = "return OHLC buckets of the business token for the range"),
+      (status = 404, description = "business does not exist", body = crate::open_api::schemas::ErrorBody)
+  )
+)]
+pub async fn get_business_chart(
+  Path(business_id): Path<i32>,
+  ValidatedQuery(query): ValidatedQuery<ChartQuery>,
+  State(state): State<AppState>,
+) -> Result<Json<Vec<candle::Data>>, AppError> {
+  let prisma_client = state.prisma_client;
+
+  let (tier, span) = match query.range {
+    ChartRange::Day => (prisma::SnapshotTier::FiveMinutes, Duration::hours(24)),
+    ChartRange::Week => (prisma::SnapshotTier::OneHour, Duration::days(7)),
+    ChartRange::Month => (prisma::SnapshotTier::OneDay, Duration::days(30)),
+  };
+  let since: DateTime<FixedOffset> = (Utc::now() - span).into();
+
+  let business = prisma_client
+    .business()
+    .find_unique(prisma::business::id::equals(business_id))
+    .select(prisma::business::select!({ id }))
+    .exec()
+    .observe("business.find_unique")
+    .await?;
+
+  if business.is_none() {
+    return Err(AppError::not_found("Business not found"));
+  }
+
+  let candles = prisma_client
+    .quote_snapshot()
+    .find_many(vec![
+      prisma::quote_snapshot::business_id::equals(business_id),
+      prisma::quote_snapshot::tier::equals(tier),
+      prisma::quote_snapshot::bucket_start::gte(since),
+    ])
+    .order_by(prisma::quote_snapshot::bucket_start::order(Direction::Asc))
+    .select(candle::select())
+    .exec()
+    .observe("quote_snapshot.find_many")
     .await?;
 
-  let tasks = future::join_all(data.iter().map(|b| async {
-    prisma_client
-      .business()
-      .find_unique(prisma::business::id::equals(b.id))
-      .select(rand_business::select())
-      .exec()
-      .await
-      .unwrap()
-  }))
-  .await;
-
-  Ok(Json(tasks))
+  Ok(Json(candles))
 }
@@ -0,0 +1,539 @@
+use crate::database::observe::ObserveQuery;
+use crate::database::prisma::{self, campaign, user_campaign, ActivityKind};
+use crate::{
+  intercept::{
+    sercurity::{AdminGuard, Guard},
+    validate::ValidatedJson,
+  },
+  services::{
+    point::{bump_leaderboards, insert_activity, ActivitySource},
+    voucher::{
+      issued_voucher, users_with_issued_vouchers, verify_claim_transaction, voucher_config, Voucher,
+    },
+  },
+  AppState,
+};
+use axum::{
+  extract::{Path, State},
+  Json,
+};
+use error::AppError;
+use ethers::types::Address;
+use prisma_client_rust::Direction;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+prisma::user_campaign::select!(my_campaign {
+  amount
+  claimed
+  txn_hash
+  campaigns: select {
+    id
+    created_at
+    title
+    description
+    metadata
+  }
+});
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct CreateCampaignPayload {
+  #[validate(length(min = 1))]
+  title: String,
+
+  #[validate(length(min = 1))]
+  description: String,
+
+  metadata: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct UpdateCampaignPayload {
+  #[validate(length(min = 1))]
+  title: Option<String>,
+
+  #[validate(length(min = 1))]
+  description: Option<String>,
+
+  metadata: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct Allocation {
+  user_id: i32,
+
+  #[validate(range(min = 1))]
+  amount: i32,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct AllocationsPayload {
+  #[validate(length(min = 1))]
+  #[validate]
+  allocations: Vec<Allocation>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AllocationsResult {
+  allocated: Vec<i32>,
+  skipped_claimed: Vec<i32>,
+  skipped_issued: Vec<i32>,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct ClaimPayload {
+  #[validate(custom = "crate::utils::validate_txn_hash")]
+  txn_hash: String,
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  post,
+  path = "/admin/campaigns",
+  tag = "campaign",
+  request_body = CreateCampaignPayload,
+  responses(
+      (status = 200, description = "return the created campaign")
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn create_campaign(
+  AdminGuard(_claims): AdminGuard,
+  State(state): State<AppState>,
+  ValidatedJson(payload): ValidatedJson<CreateCampaignPayload>,
+) -> Result<Json<campaign::Data>, AppError> {
+  let CreateCampaignPayload {
+    title,
+    description,
+    metadata,
+  } = payload;
+
+  let campaign = state
+    .prisma_client
+    .campaign()
+    .create(title, description, vec![campaign::metadata::set(metadata)])
+    .exec()
+    .observe("campaign.create")
+    .await?;
+
+  Ok(Json(campaign))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  get,
+  path = "/admin/campaigns",
+  tag = "campaign",
+  responses(
+      (status = 200, description = "return all campaigns")
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn get_campaigns(
+  AdminGuard(_claims): AdminGuard,
+  State(state): State<AppState>,
+) -> Result<Json<Vec<campaign::Data>>, AppError> {
+  let campaigns = state
+    .prisma_client
+    .campaign()
+    .find_many(vec![])
+    .order_by(campaign::created_at::order(Direction::Desc))
+    .exec()
+    .observe("campaign.find_many")
+    .await?;
+
+  Ok(Json(campaigns))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  patch,
+  path = "/admin/campaigns/{id}",
+  tag = "campaign",
+  params(
+    ("id" = i32, Path, description = "campaign id")
+  ),
+  request_body = UpdateCampaignPayload,
+  responses(
+      (status = 200, description = "return the updated campaign"),
+      (status = 404, description = "campaign does not exist", body = crate::open_api::schemas::ErrorBody)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn update_campaign(
+  AdminGuard(_claims): AdminGuard,
+  Path(campaign_id): Path<i32>,
+  State(state): State<AppState>,
+  ValidatedJson(payload): ValidatedJson<UpdateCampaignPayload>,
+) -> Result<Json<campaign::Data>, AppError> {
+  let prisma_client = state.prisma_client;
+  let UpdateCampaignPayload {
+    title,
+    description,
+    metadata,
+  } = payload;
+
+  let mut params = vec![];
+
+  if let Some(title) = title {
+    params.push(campaign::title::set(title));
+  }
+
+  if let Some(description) = description {
+    params.push(campaign::description::set(description));
+  }
+
+  if metadata.is_some() {
+    params.push(campaign::metadata::set(metadata));
+  }
+
+  let exists = prisma_client
+    .campaign()
+    .find_unique(campaign::id::equals(campaign_id))
+    .exec()
+    .observe("campaign.find_unique")
+    .await?
+    .is_some();
+
+  if !exists {
+    return Err(AppError::not_found("Campaign not found"));
+  }
+
+  let campaign = prisma_client
+    .campaign()
+    .update(campaign::id::equals(campaign_id), params)
+    .exec()
+    .observe("campaign.update")
+    .await?;
+
+  Ok(Json(campaign))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  delete,
+  path = "/admin/campaigns/{id}",
+  tag = "campaign",
+  params(
+    ("id" = i32, Path, description = "campaign id")
+  ),
+  responses(
+      (status = 200, description = "campaign was deleted"),
+      (status = 404, description = "campaign does not exist", body = crate::open_api::schemas::ErrorBody),
+      (status = 409, description = "campaign already has claimed rewards", body = crate::open_api::schemas::ErrorBody)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn delete_campaign(
+  AdminGuard(_claims): AdminGuard,
+  Path(campaign_id): Path<i32>,
+  State(state): State<AppState>,
+) -> Result<(), AppError> {
+  let prisma_client = state.prisma_client;
+
+  let (campaign, claimed) = tokio::join!(
+    prisma_client
+      .campaign()
+      .find_unique(campaign::id::equals(campaign_id))
+      .exec()
+      .observe("campaign.find_unique"),
+    prisma_client
+      .user_campaign()
+      .count(vec![
+        user_campaign::campaign_id::equals(campaign_id),
+        user_campaign::claimed::equals(true),
+      ])
+      .exec()
+      .observe("user_campaign.count")
+  );
+
+  if campaign?.is_none() {
+    return Err(AppError::not_found("Campaign not found"));
+  }
+
+  if claimed? > 0 {
+    return Err(AppError::conflict("Campaign already has claimed rewards"));
+  }
+
+  prisma_client
+    .campaign()
+    .delete(campaign::id::equals(campaign_id))
+    .exec()
+    .observe("campaign.delete")
+    .await?;
+
+  Ok(())
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  put,
+  path = "/admin/campaigns/{id}/allocations",
+  tag = "campaign",
+  params(
+    ("id" = i32, Path, description = "campaign id")
+  ),
+  request_body = AllocationsPayload,
+  responses(
+      (status = 200, description = "return allocated users and users skipped because they already claimed or were issued a voucher", body = AllocationsResult),
+      (status = 404, description = "campaign does not exist", body = crate::open_api::schemas::ErrorBody)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn allocate_campaign(
+  AdminGuard(_claims): AdminGuard,
+  Path(campaign_id): Path<i32>,
+  State(state): State<AppState>,
+  ValidatedJson(payload): ValidatedJson<AllocationsPayload>,
+) -> Result<Json<AllocationsResult>, AppError> {
+  let AppState {
+    prisma_client,
+    mut redis_conn,
+    ..
+  } = state;
+
+  prisma_client
+    .campaign()
+    .find_unique(campaign::id::equals(campaign_id))
+    .exec()
+    .observe("campaign.find_unique")
+    .await?
+    .ok_or_else(|| AppError::not_found("Campaign not found"))?;
+
+  // A voucher already handed out redeems the amount it was signed for, that allocation stays as is.
+  let user_ids: Vec<i32> = payload
+    .allocations
+    .iter()
+    .map(|allocation| allocation.user_id)
+    .collect();
+  let skipped_issued = users_with_issued_vouchers(&mut redis_conn, campaign_id, &user_ids).await?;
+  let allocations: Vec<Allocation> = payload
+    .allocations
+    .into_iter()
+    .filter(|allocation| !skipped_issued.contains(&allocation.user_id))
+    .collect();
+
+  // Only unclaimed allocations are updated, so a claim committing concurrently keeps the amount it
+  // was paid out with. An allocation that is left untouched but exists has been claimed.
+  let (allocated, skipped_claimed) = prisma_client
+    ._transaction()
+    .run(|tx| async move {
+      let mut allocated = vec![];
+      let mut skipped_claimed = vec![];
+
+      for Allocation { user_id, amount } in allocations {
+        let updated = tx
+          .user_campaign()
+          .update_many(
+            vec![
+              user_campaign::user_id::equals(user_id),
+              user_campaign::campaign_id::equals(campaign_id),
+              user_campaign::claimed::equals(false),
+            ],
+            vec![user_campaign::amount::set(amount)],
+          )
+          .exec()
+          .observe("user_campaign.update_many")
+          .await?;
+
+        if updated > 0 {
+          allocated.push(user_id);
+          continue;
+        }
+
+        let existing = tx
+          .user_campaign()
+          .find_unique(user_campaign::user_id_campaign_id(user_id, campaign_id))
+          .exec()
+          .observe("user_campaign.find_unique")
+          .await?;
+
+        if existing.is_some() {
+          skipped_claimed.push(user_id);
+          continue;
+        }
+
+        tx.user_campaign()
+          .create(
+            amount,
+            campaign::id::equals(campaign_id),
+            prisma::user::id::equals(user_id),
+            vec![],
+          )
+          .exec()
+          .observe("user_campaign.create")
+          .await?;
+
+        allocated.push(user_id);
+      }
+
+      Ok::<_, AppError>((allocated, skipped_claimed))
+    })
+    .await?;
+
+  Ok(Json(AllocationsResult {
+    allocated,
+    skipped_claimed,
+    skipped_issued,
+  }))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  get,
+  path = "/campaigns",
+  tag = "campaign",
+  responses(
+      (status = 200, description = "return campaigns you are eligible for with allocated amounts")
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn get_my_campaigns(
+  Guard(claims): Guard,
+  State(state): State<AppState>,
+) -> Result<Json<Vec<my_campaign::Data>>, AppError> {
+  let campaigns = state
+    .prisma_client
+    .user_campaign()
+    .find_many(vec![user_campaign::user_id::equals(claims.id)])
+    .select(my_campaign::select())
+    .exec()
+    .observe("user_campaign.find_many")
+    .await?;
+
+  Ok(Json(campaigns))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  post,
+  path = "/campaigns/{id}/claim",
+  tag = "campaign",
+  params(
+    ("id" = i32, Path, description = "campaign id")
+  ),
+  request_body = ClaimPayload,
+  responses(
+      (status = 200, description = "reward was claimed"),
+      (status = 400, description = "transaction does not redeem this reward on chain", body = crate::open_api::schemas::ErrorBody),
+      (status = 404, description = "you have no allocation in this campaign", body = crate::open_api::schemas::ErrorBody),
+      (status = 409, description = "reward was already claimed", body = crate::open_api::schemas::ErrorBody),
+      (status = 503, description = "rewards are not enabled", body = crate::open_api::schemas::ErrorBody)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn claim_campaign(
+  Guard(claims): Guard,
+  Path(campaign_id): Path<i32>,
+  State(state): State<AppState>,
+  ValidatedJson(payload): ValidatedJson<ClaimPayload>,
+) -> Result<Json<my_campaign::Data>, AppError> {
+  let AppState {
+    prisma_client,
+    mut redis_conn,
+    config,
+    ..
+  } = state;
+  let voucher_config = voucher_config(&config)?;
+
+  let allocation = prisma_client
+    .user_campaign()
+    .find_unique(user_campaign::user_id_campaign_id(claims.id, campaign_id))
+    .exec()
+    .observe("user_campaign.find_unique")
+    .await?
+    .ok_or_else(|| AppError::not_found("No allocation for this campaign"))?;
+
+  if allocation.claimed {
+    return Err(AppError::conflict("Reward was already claimed"));
+  }
+
+  let issued = issued_voucher(&mut redis_conn, claims.id, campaign_id)
+    .await?
+    .ok_or_else(|| AppError::bad_request("No voucher was issued for this campaign"))?;
+
+  // The deadline is not part of the claim event, the contract already refused expired vouchers.
+  let expected = Voucher {
+    user: claims.wallet_address.parse::<Address>()?,
+    campaign_id,
+    amount: issued.amount,
+    nonce: issued.nonce,
+    deadline: 0,
+  };
+
+  verify_claim_transaction(voucher_config, &payload.txn_hash, &expected).await?;
+
+  let user_id = claims.id;
+  let txn_hash = payload.txn_hash;
+
+  // The claim and its ledger entry commit together, a failed insert leaves the reward claimable.
+  prisma_client
+    ._transaction()
+    .run(|tx| async move {
+      let claimed = tx
+        .user_campaign()
+        .update_many(
+          vec![
+            user_campaign::user_id::equals(user_id),
+            user_campaign::campaign_id::equals(campaign_id),
+            user_campaign::claimed::equals(false),
+          ],
+          vec![
+            user_campaign::claimed::set(true),
+            user_campaign::amount::set(issued.amount),
+            user_campaign::txn_hash::set(Some(txn_hash)),
+          ],
+        )
+        .exec()
+        .observe("user_campaign.update_many")
+        .await?;
+
+      if claimed == 0 {
+        return Err(AppError::conflict("Reward was already claimed"));
+      }
+
+      insert_activity(
+        &tx,
+        user_id,
+        ActivityKind::Reward,
+        ActivitySource::Campaign(campaign_id),
+      )
+      .await?;
+
+      Ok(())
+    })
+    .await?;
+
+  let user_campaign = prisma_client
+    .user_campaign()
+    .find_unique(user_campaign::user_id_campaign_id(claims.id, campaign_id))
+    .select(my_campaign::select())
+    .exec()
+    .observe("user_campaign.find_unique")
+    .await?
+    .ok_or_else(|| AppError::not_found("No allocation for this campaign"))?;
+
+  // The claim is committed, a stale leaderboard is rebuilt once its sorted set expires.
+  if let Err(err) = bump_leaderboards(&mut redis_conn, user_id, ActivityKind::Reward).await {
+    tracing::warn!(user_id, campaign_id, error = %err, "bumping leaderboards failed");
+  }
+
+  Ok(Json(user_campaign))
+}
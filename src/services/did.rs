@@ -0,0 +1,444 @@
+use crate::database::observe::ObserveQuery;
+use crate::database::prisma::{self, did, PrismaClient};
+use crate::{
+  intercept::{did::invalidate_did_cache, sercurity::Guard, validate::ValidatedJson},
+  services::auth::{check_siwe_message, handle_address, verify_siwe},
+  AppState,
+};
+use axum::{
+  extract::{Path, State},
+  Json,
+};
+use error::AppError;
+use serde::{Deserialize, Serialize};
+use siwe::Message;
+use utoipa::ToSchema;
+use validator::Validate;
+
+prisma::did::select!(did_detail {
+  id
+  controller
+  username
+  email
+  users: select {
+    id
+    wallet_address
+  }
+});
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct CreateDidPayload {
+  #[validate(length(min = 3, max = 32))]
+  username: String,
+
+  #[validate(email)]
+  email: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct LinkWalletPayload {
+  #[validate(length(min = 1))]
+  message: String,
+
+  #[validate(length(min = 1))]
+  signature: String,
+}
+
+// What the wallet to link has to sign, as the domain, nonce and statement of a SIWE message with an
+// expiration time.
+#[derive(Serialize, ToSchema)]
+pub struct LinkChallenge {
+  domain: String,
+  nonce: String,
+  statement: String,
+  // Seconds the nonce can be used for.
+  expires_in: u64,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct TransferControllerPayload {
+  #[validate(length(min = 1))]
+  address: String,
+}
+
+const LINK_NONCE_TTL_SECONDS: u64 = 5 * 60;
+
+fn link_nonce_key(user_id: i32) -> String {
+  format!("did_link_nonce:{user_id}")
+}
+
+// Names the DID, so that a signature cannot link the wallet to another one.
+fn link_statement(did_id: i32) -> String {
+  format!("Link this wallet to DID #{did_id}")
+}
+
+fn member_ids(did: &did_detail::Data) -> Vec<i32> {
+  did.users.iter().map(|u| u.id).collect()
+}
+
+// Only the controller wallet of a DID is allowed to manage it.
+async fn controlled_did(
+  prisma_client: &PrismaClient,
+  wallet_address: &str,
+) -> Result<did_detail::Data, AppError> {
+  prisma_client
+    .did()
+    .find_unique(did::controller::equals(wallet_address.to_string()))
+    .select(did_detail::select())
+    .exec()
+    .observe("did.find_unique")
+    .await?
+    .ok_or_else(|| AppError::forbidden("Only the DID controller can manage it"))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  post,
+  path = "/dids",
+  tag = "did",
+  request_body = CreateDidPayload,
+  responses(
+      (status = 200, description = "return the created DID controlled by your wallet"),
+      (status = 409, description = "username is taken or your wallet already belongs to a DID", body = crate::open_api::schemas::ErrorBody)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn create_did(
+  Guard(claims): Guard,
+  State(state): State<AppState>,
+  ValidatedJson(payload): ValidatedJson<CreateDidPayload>,
+) -> Result<Json<did_detail::Data>, AppError> {
+  let AppState {
+    prisma_client,
+    mut redis_conn,
+    ..
+  } = state;
+  let CreateDidPayload { username, email } = payload;
+
+  let (user, username_taken) = tokio::join!(
+    prisma_client
+      .user()
+      .find_unique(prisma::user::id::equals(claims.id))
+      .select(prisma::user::select!({ did_id }))
+      .exec()
+      .observe("user.find_unique"),
+    prisma_client
+      .did()
+      .find_unique(did::username::equals(Some(username.to_owned())))
+      .exec()
+      .observe("did.find_unique")
+  );
+
+  if user?.and_then(|u| u.did_id).is_some() {
+    return Err(AppError::conflict("Your wallet already belongs to a DID"));
+  }
+
+  if username_taken?.is_some() {
+    return Err(AppError::conflict("Username is already taken"));
+  }
+
+  let did = prisma_client
+    .did()
+    .create(
+      claims.wallet_address,
+      vec![
+        did::username::set(Some(username)),
+        did::email::set(email),
+        did::users::connect(vec![prisma::user::id::equals(claims.id)]),
+      ],
+    )
+    .select(did_detail::select())
+    .exec()
+    .observe("did.create")
+    .await?;
+
+  invalidate_did_cache(&mut redis_conn, &member_ids(&did)).await;
+
+  Ok(Json(did))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  get,
+  path = "/dids/wallets/nonce",
+  tag = "did",
+  responses(
+      (status = 200, description = "return what the wallet to link has to sign, replaces the previous nonce", body = LinkChallenge),
+      (status = 403, description = "you are not the DID controller", body = crate::open_api::schemas::ErrorBody)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn get_link_challenge(
+  Guard(claims): Guard,
+  State(state): State<AppState>,
+) -> Result<Json<LinkChallenge>, AppError> {
+  let AppState {
+    prisma_client,
+    mut redis_conn,
+    config,
+    ..
+  } = state;
+  let did = controlled_did(&prisma_client, &claims.wallet_address).await?;
+  let nonce = siwe::generate_nonce();
+
+  redis::cmd("SET")
+    .arg(link_nonce_key(claims.id))
+    .arg(&nonce)
+    .arg("EX")
+    .arg(LINK_NONCE_TTL_SECONDS)
+    .query_async::<_, ()>(&mut redis_conn)
+    .await?;
+
+  Ok(Json(LinkChallenge {
+    domain: config.auth.siwe_domain.to_owned(),
+    nonce,
+    statement: link_statement(did.id),
+    expires_in: LINK_NONCE_TTL_SECONDS,
+  }))
+}
+
+// Like a login message, and it has to name the DID it was issued for.
+fn check_link_message(
+  message: &Message,
+  nonce: Option<&str>,
+  domain: &str,
+  did_id: i32,
+) -> Result<(), AppError> {
+  check_siwe_message(message, nonce, domain)?;
+
+  if message.statement.as_deref() != Some(link_statement(did_id).as_str()) {
+    return Err(AppError::bad_request("Message does not name your DID"));
+  }
+
+  Ok(())
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  post,
+  path = "/dids/wallets",
+  tag = "did",
+  request_body = LinkWalletPayload,
+  responses(
+      (status = 200, description = "wallet proven by the SIWE signature was linked, return the DID"),
+      (status = 400, description = "signature is invalid or the message does not match the issued nonce", body = crate::open_api::schemas::ErrorBody),
+      (status = 403, description = "you are not the DID controller", body = crate::open_api::schemas::ErrorBody),
+      (status = 409, description = "wallet already belongs to a DID", body = crate::open_api::schemas::ErrorBody)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn link_wallet(
+  Guard(claims): Guard,
+  State(state): State<AppState>,
+  ValidatedJson(payload): ValidatedJson<LinkWalletPayload>,
+) -> Result<Json<did_detail::Data>, AppError> {
+  let AppState {
+    prisma_client,
+    mut redis_conn,
+    config,
+    ..
+  } = state;
+  let did = controlled_did(&prisma_client, &claims.wallet_address).await?;
+
+  let message = payload
+    .message
+    .parse::<Message>()
+    .map_err(|_| AppError::bad_request("Invalid SIWE message"))?;
+
+  // Taken before anything is checked, a nonce is spent by any attempt.
+  let nonce = redis::cmd("GETDEL")
+    .arg(link_nonce_key(claims.id))
+    .query_async::<_, Option<String>>(&mut redis_conn)
+    .await?;
+
+  check_link_message(&message, nonce.as_deref(), &config.auth.siwe_domain, did.id)?;
+
+  let wallet_address = match verify_siwe(&payload.message, &payload.signature) {
+    Ok(wallet_address) => wallet_address,
+    Err(_) => return Err(AppError::bad_request("Invalid signature")),
+  };
+
+  let wallet_user = handle_address(wallet_address, prisma_client.clone()).await?;
+
+  let already_linked = prisma_client
+    .user()
+    .find_first(vec![
+      prisma::user::id::equals(wallet_user.id),
+      prisma::user::did_id::not(None),
+    ])
+    .exec()
+    .observe("user.find_first")
+    .await?
+    .is_some();
+
+  if already_linked {
+    return Err(AppError::conflict("Wallet already belongs to a DID"));
+  }
+
+  prisma_client
+    .user()
+    .update(
+      prisma::user::id::equals(wallet_user.id),
+      vec![prisma::user::did::connect(did::id::equals(did.id))],
+    )
+    .exec()
+    .observe("user.update")
+    .await?;
+
+  let did = controlled_did(&prisma_client, &claims.wallet_address).await?;
+  invalidate_did_cache(&mut redis_conn, &member_ids(&did)).await;
+
+  Ok(Json(did))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  delete,
+  path = "/dids/wallets/{address}",
+  tag = "did",
+  params(
+    ("address" = String, Path, description = "wallet address to unlink")
+  ),
+  responses(
+      (status = 200, description = "wallet was unlinked, return the DID"),
+      (status = 400, description = "the controller wallet cannot be unlinked", body = crate::open_api::schemas::ErrorBody),
+      (status = 403, description = "you are not the DID controller", body = crate::open_api::schemas::ErrorBody),
+      (status = 404, description = "wallet is not linked to your DID", body = crate::open_api::schemas::ErrorBody)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn unlink_wallet(
+  Guard(claims): Guard,
+  Path(address): Path<String>,
+  State(state): State<AppState>,
+) -> Result<Json<did_detail::Data>, AppError> {
+  let AppState {
+    prisma_client,
+    mut redis_conn,
+    ..
+  } = state;
+  let did = controlled_did(&prisma_client, &claims.wallet_address).await?;
+
+  let linked_user = did
+    .users
+    .iter()
+    .find(|u| u.wallet_address.eq_ignore_ascii_case(&address))
+    .ok_or_else(|| AppError::not_found("Wallet is not linked to your DID"))?;
+
+  if linked_user.wallet_address == did.controller {
+    return Err(AppError::bad_request(
+      "Transfer the controller before unlinking its wallet",
+    ));
+  }
+
+  prisma_client
+    .user()
+    .update(
+      prisma::user::id::equals(linked_user.id),
+      vec![prisma::user::did::disconnect()],
+    )
+    .exec()
+    .observe("user.update")
+    .await?;
+
+  invalidate_did_cache(&mut redis_conn, &member_ids(&did)).await;
+  let did = controlled_did(&prisma_client, &claims.wallet_address).await?;
+
+  Ok(Json(did))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  patch,
+  path = "/dids/controller",
+  tag = "did",
+  request_body = TransferControllerPayload,
+  responses(
+      (status = 200, description = "controller was transferred, return the DID"),
+      (status = 403, description = "you are not the DID controller", body = crate::open_api::schemas::ErrorBody),
+      (status = 404, description = "new controller is not linked to your DID", body = crate::open_api::schemas::ErrorBody)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn transfer_controller(
+  Guard(claims): Guard,
+  State(state): State<AppState>,
+  ValidatedJson(payload): ValidatedJson<TransferControllerPayload>,
+) -> Result<Json<did_detail::Data>, AppError> {
+  let AppState {
+    prisma_client,
+    mut redis_conn,
+    ..
+  } = state;
+  let did = controlled_did(&prisma_client, &claims.wallet_address).await?;
+
+  let new_controller = did
+    .users
+    .iter()
+    .find(|u| u.wallet_address.eq_ignore_ascii_case(&payload.address))
+    .ok_or_else(|| AppError::not_found("New controller is not linked to your DID"))?;
+
+  let did = prisma_client
+    .did()
+    .update(
+      did::id::equals(did.id),
+      vec![did::controller::set(new_controller.wallet_address.to_owned())],
+    )
+    .select(did_detail::select())
+    .exec()
+    .observe("did.update")
+    .await?;
+
+  invalidate_did_cache(&mut redis_conn, &member_ids(&did)).await;
+
+  Ok(Json(did))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn link_message(domain: &str, did_id: i32, expiration_time: &str) -> Message {
+    format!(
+      "{domain} wants you to sign in with your Ethereum account:\n\
+       0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2\n\
+       \n\
+       {}\n\
+       \n\
+       URI: https://{domain}\n\
+       Version: 1\n\
+       Chain ID: 1\n\
+       Nonce: k3mR7qLx2wZp\n\
+       Issued At: 2023-06-01T00:00:00Z\n\
+       Expiration Time: {expiration_time}",
+      link_statement(did_id)
+    )
+    .parse()
+    .unwrap()
+  }
+
+  #[test]
+  fn link_message_is_bound_to_nonce_domain_did_and_expiry() {
+    let domain = "localhost:3000";
+    let valid = link_message(domain, 7, "2100-01-01T00:00:00Z");
+
+    assert!(check_link_message(&valid, Some("k3mR7qLx2wZp"), domain, 7).is_ok());
+    assert!(check_link_message(&valid, None, domain, 7).is_err());
+    assert!(check_link_message(&valid, Some("x9vB4nTq8sYe"), domain, 7).is_err());
+    assert!(check_link_message(&valid, Some("k3mR7qLx2wZp"), "app.example.com", 7).is_err());
+    assert!(check_link_message(&valid, Some("k3mR7qLx2wZp"), domain, 8).is_err());
+
+    let expired = link_message(domain, 7, "2023-06-01T00:05:00Z");
+    assert!(check_link_message(&expired, Some("k3mR7qLx2wZp"), domain, 7).is_err());
+  }
+}
@@ -0,0 +1,116 @@
+use crate::database::observe::ObserveQuery;
+use crate::{shutdown, AppState};
+use axum::{extract::State, http::StatusCode, Json};
+use prisma_client_rust::raw;
+use serde::Serialize;
+use std::{fmt::Display, future::Future, time::Duration};
+use utoipa::ToSchema;
+
+// Kept below the probe timeout so that a hanging dependency is reported instead of killing the probe.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Readiness {
+  ready: bool,
+  shutting_down: bool,
+  postgres: DependencyCheck,
+  redis: DependencyCheck,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DependencyCheck {
+  ok: bool,
+  latency_ms: u64,
+}
+
+// The probe is public, so the failure reason only goes to the logs.
+async fn check<E: Display>(
+  name: &str,
+  probe: impl Future<Output = Result<(), E>>,
+) -> DependencyCheck {
+  let started = tokio::time::Instant::now();
+
+  let ok = match tokio::time::timeout(CHECK_TIMEOUT, probe).await {
+    Ok(Ok(())) => true,
+    Ok(Err(err)) => {
+      tracing::warn!(dependency = name, error = %err, "readiness check failed");
+      false
+    }
+    Err(_) => {
+      tracing::warn!(dependency = name, timeout = ?CHECK_TIMEOUT, "readiness check timed out");
+      false
+    }
+  };
+
+  DependencyCheck {
+    ok,
+    latency_ms: started.elapsed().as_millis() as u64,
+  }
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  get,
+  path = "/healthz",
+  tag = "health",
+  responses(
+      (status = 200, description = "the process is alive", body = String, content_type = "text/plain")
+  )
+)]
+pub async fn healthz() -> &'static str {
+  "ok"
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  get,
+  path = "/readyz",
+  tag = "health",
+  responses(
+      (status = 200, description = "postgres and redis are reachable", body = Readiness),
+      (status = 503, description = "postgres or redis is unreachable, or the process is shutting down", body = Readiness)
+  )
+)]
+pub async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<Readiness>) {
+  let AppState {
+    prisma_client,
+    mut redis_conn,
+    ..
+  } = state;
+
+  let (postgres, redis) = tokio::join!(
+    check("postgres", async {
+      prisma_client
+        ._query_raw::<serde_json::Value>(raw!("SELECT 1"))
+        .exec()
+        .observe("query_raw.readyz")
+        .await
+        .map(|_| ())
+    }),
+    check("redis", async {
+      redis::cmd("PING")
+        .query_async::<_, String>(&mut redis_conn)
+        .await
+        .map(|_| ())
+    })
+  );
+
+  let shutting_down = shutdown::started();
+  let ready = !shutting_down && postgres.ok && redis.ok;
+
+  (
+    if ready {
+      StatusCode::OK
+    } else {
+      StatusCode::SERVICE_UNAVAILABLE
+    },
+    Json(Readiness {
+      ready,
+      shutting_down,
+      postgres,
+      redis,
+    }),
+  )
+}
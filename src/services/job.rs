@@ -0,0 +1,54 @@
+use crate::{intercept::sercurity::AdminGuard, schedulers::registry::JobStatus, AppState};
+use axum::{
+  extract::{Path, State},
+  http::StatusCode,
+  Json,
+};
+use error::AppError;
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  get,
+  path = "/admin/jobs",
+  tag = "job",
+  responses(
+      (status = 200, description = "return every registered job with its last run", body = [JobStatus])
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn get_jobs(
+  AdminGuard(_claims): AdminGuard,
+  State(state): State<AppState>,
+) -> Result<Json<Vec<JobStatus>>, AppError> {
+  Ok(Json(state.jobs.statuses().await?))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  post,
+  path = "/admin/jobs/{name}/run",
+  tag = "job",
+  params(
+    ("name" = String, Path, description = "job name")
+  ),
+  responses(
+      (status = 202, description = "job was started in the background, its outcome shows up in the job list"),
+      (status = 404, description = "job does not exist", body = crate::open_api::schemas::ErrorBody)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn run_job(
+  AdminGuard(_claims): AdminGuard,
+  Path(name): Path<String>,
+  State(state): State<AppState>,
+) -> Result<StatusCode, AppError> {
+  if !state.jobs.trigger(&name) {
+    return Err(AppError::not_found("Job not found"));
+  }
+
+  Ok(StatusCode::ACCEPTED)
+}
@@ -0,0 +1,527 @@
+use crate::database::observe::ObserveQuery;
+use crate::database::prisma::{self, activity, ActivityKind, PrismaClient};
+use crate::database::redis_conn::RedisConn;
+use crate::{
+  intercept::{sercurity::Guard, validate::ValidatedQuery},
+  AppState,
+};
+use anyhow::Result;
+use axum::{extract::State, Json};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, TimeZone, Utc};
+use error::AppError;
+use prisma_client_rust::{
+  prisma_errors::query_engine::UniqueKeyViolation, raw, Direction, PrismaValue, QueryError,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+// Rebuilt from Postgres whenever the sorted set expires, so drift never outlives this.
+const LEADERBOARD_TTL_SECONDS: usize = 60 * 60;
+
+// Only bump a leaderboard that is already materialized, a missing one gets rebuilt on read.
+const BUMP_LEADERBOARD_SCRIPT: &str = r#"
+  if redis.call("EXISTS", KEYS[1]) == 1 then
+    return redis.call("ZINCRBY", KEYS[1], ARGV[1], ARGV[2])
+  end
+  return nil
+"#;
+
+pub enum ActivitySource {
+  Review(i32),
+  Campaign(i32),
+  Platform(String),
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LeaderboardPeriod {
+  Weekly,
+  Monthly,
+  #[default]
+  AllTime,
+}
+
+impl LeaderboardPeriod {
+  const ALL: [LeaderboardPeriod; 3] = [
+    LeaderboardPeriod::Weekly,
+    LeaderboardPeriod::Monthly,
+    LeaderboardPeriod::AllTime,
+  ];
+
+  fn start(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    match self {
+      LeaderboardPeriod::Weekly => {
+        let monday =
+          now.date_naive() - Duration::days(now.weekday().num_days_from_monday() as i64);
+        Some(Utc.from_utc_datetime(&monday.and_hms_opt(0, 0, 0).unwrap()))
+      }
+      LeaderboardPeriod::Monthly => Some(
+        Utc
+          .with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0)
+          .unwrap(),
+      ),
+      LeaderboardPeriod::AllTime => None,
+    }
+  }
+
+  fn redis_key(&self, now: DateTime<Utc>) -> String {
+    match self {
+      LeaderboardPeriod::Weekly => {
+        let week = now.iso_week();
+        format!("leaderboard:weekly:{}-W{:02}", week.year(), week.week())
+      }
+      LeaderboardPeriod::Monthly => {
+        format!("leaderboard:monthly:{}-{:02}", now.year(), now.month())
+      }
+      LeaderboardPeriod::AllTime => "leaderboard:all_time".to_string(),
+    }
+  }
+}
+
+fn activity_name(kind: ActivityKind) -> &'static str {
+  match kind {
+    ActivityKind::Reviewapproved => "reviewapproved",
+    ActivityKind::Reacthelpful => "reacthelpful",
+    ActivityKind::Reactdownful => "reactdownful",
+    ActivityKind::Reply => "reply",
+    ActivityKind::Share => "share",
+    ActivityKind::JoinDiscord => "join_discord",
+    ActivityKind::JoinTelegram => "join_telegram",
+    ActivityKind::Reward => "reward",
+  }
+}
+
+pub fn activity_point(kind: ActivityKind) -> i32 {
+  match kind {
+    ActivityKind::Reviewapproved => 20,
+    ActivityKind::Reacthelpful => 2,
+    ActivityKind::Reactdownful => 0,
+    ActivityKind::Reply => 2,
+    ActivityKind::Share => 1,
+    ActivityKind::JoinDiscord => 5,
+    ActivityKind::JoinTelegram => 5,
+    ActivityKind::Reward => 10,
+  }
+}
+
+// Every user earns an activity once per source. Join activities are in addition unique per platform
+// account in the schema, so that an account relinked to another user earns nothing a second time.
+fn idempotency_key(kind: ActivityKind, source: &ActivitySource, user_id: i32) -> String {
+  let source = match source {
+    ActivitySource::Review(review_id) => format!("review:{review_id}"),
+    ActivitySource::Campaign(campaign_id) => format!("campaign:{campaign_id}"),
+    ActivitySource::Platform(platform_id) => format!("platform:{platform_id}"),
+  };
+
+  format!("{}:{source}:user:{user_id}", activity_name(kind))
+}
+
+// Records an activity at most once per kind and source, returns false when it already exists.
+pub async fn record_activity(
+  prisma_client: &PrismaClient,
+  redis_conn: &mut RedisConn,
+  user_id: i32,
+  kind: ActivityKind,
+  source: ActivitySource,
+) -> Result<bool> {
+  let created = insert_activity(prisma_client, user_id, kind, source).await?;
+
+  if created {
+    bump_leaderboards(redis_conn, user_id, kind).await?;
+  }
+
+  Ok(created)
+}
+
+// The ledger half of `record_activity`, for callers that insert the activity inside their own transaction.
+// Leaderboards must only be bumped once that transaction committed.
+pub async fn insert_activity(
+  prisma_client: &PrismaClient,
+  user_id: i32,
+  kind: ActivityKind,
+  source: ActivitySource,
+) -> Result<bool, QueryError> {
+  let point = activity_point(kind);
+  let idempotency_key = idempotency_key(kind, &source, user_id);
+
+  let source_param = match source {
+    ActivitySource::Review(review_id) => {
+      activity::reviews::connect(prisma::review::id::equals(review_id))
+    }
+    ActivitySource::Campaign(campaign_id) => {
+      activity::campaigns::connect(prisma::campaign::id::equals(campaign_id))
+    }
+    ActivitySource::Platform(platform_id) => activity::platform_id::set(Some(platform_id)),
+  };
+
+  let created = prisma_client
+    .activity()
+    .create(
+      kind,
+      point,
+      prisma::user::id::equals(user_id),
+      vec![
+        activity::idempotency_key::set(Some(idempotency_key)),
+        source_param,
+      ],
+    )
+    .exec()
+    .observe("activity.create")
+    .await;
+
+  match created {
+    Ok(_) => Ok(true),
+    Err(err) if err.is_prisma_error::<UniqueKeyViolation>() => Ok(false),
+    Err(err) => Err(err),
+  }
+}
+
+pub async fn bump_leaderboards(
+  redis_conn: &mut RedisConn,
+  user_id: i32,
+  kind: ActivityKind,
+) -> Result<()> {
+  let point = activity_point(kind);
+  let now = Utc::now();
+  let script = redis::Script::new(BUMP_LEADERBOARD_SCRIPT);
+
+  for period in LeaderboardPeriod::ALL {
+    script
+      .key(period.redis_key(now))
+      .arg(point)
+      .arg(user_id)
+      .invoke_async::<_, Option<f64>>(redis_conn)
+      .await?;
+  }
+
+  Ok(())
+}
+
+#[tracing::instrument(skip(prisma_client, redis_conn, now))]
+async fn rebuild_leaderboard(
+  prisma_client: &PrismaClient,
+  redis_conn: &mut RedisConn,
+  period: LeaderboardPeriod,
+  now: DateTime<Utc>,
+) -> Result<()> {
+  #[derive(Deserialize)]
+  struct UserPoints {
+    user_id: i32,
+    points: i32,
+  }
+
+  let scores = match period.start(now) {
+    Some(start) => {
+      let start: DateTime<FixedOffset> = start.into();
+      prisma_client
+        ._query_raw::<UserPoints>(raw!(
+          r#"
+          SELECT "user_id", CAST(SUM("point") AS INTEGER) AS "points"
+          FROM "activity"
+          WHERE "created_at" >= {}
+          GROUP BY "user_id"
+          "#,
+          PrismaValue::DateTime(start)
+        ))
+        .exec()
+        .observe("query_raw.rebuild_leaderboard")
+        .await?
+    }
+    None => {
+      prisma_client
+        ._query_raw::<UserPoints>(raw!(
+          r#"
+          SELECT "user_id", CAST(SUM("point") AS INTEGER) AS "points"
+          FROM "activity"
+          GROUP BY "user_id"
+          "#
+        ))
+        .exec()
+        .observe("query_raw.rebuild_leaderboard")
+        .await?
+    }
+  };
+
+  let key = period.redis_key(now);
+
+  if scores.is_empty() {
+    redis::cmd("DEL")
+      .arg(&key)
+      .query_async::<_, ()>(redis_conn)
+      .await?;
+    return Ok(());
+  }
+
+  let staging_key = format!("{key}:rebuild");
+  let mut zadd = redis::cmd("ZADD");
+  zadd.arg(&staging_key);
+  for UserPoints { user_id, points } in scores {
+    zadd.arg(points).arg(user_id);
+  }
+
+  redis::pipe()
+    .atomic()
+    .cmd("DEL")
+    .arg(&staging_key)
+    .ignore()
+    .add_command(zadd)
+    .ignore()
+    .cmd("RENAME")
+    .arg(&staging_key)
+    .arg(&key)
+    .ignore()
+    .cmd("EXPIRE")
+    .arg(&key)
+    .arg(LEADERBOARD_TTL_SECONDS)
+    .ignore()
+    .query_async::<_, ()>(redis_conn)
+    .await?;
+
+  Ok(())
+}
+
+prisma::activity::select!(point_history {
+  id
+  created_at
+  kind
+  point
+  review_id
+  campaign_id
+  platform_id
+});
+
+prisma::user::select!(leaderboard_user {
+  id
+  wallet_address
+  nickname
+  avatar_url
+});
+
+#[derive(Deserialize, IntoParams, Validate)]
+#[into_params(parameter_in = Query)]
+pub struct PointHistoryQuery {
+  #[validate(range(min = 1))]
+  page: Option<i64>,
+
+  #[validate(range(min = 1, max = 100))]
+  limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MyPoints {
+  total: i32,
+  weekly: i32,
+  monthly: i32,
+  history: Vec<point_history::Data>,
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  get,
+  params(
+    PointHistoryQuery
+  ),
+  path = "/users/me/points",
+  tag = "point",
+  responses(
+      (status = 200, description = "return your point totals and activity history")
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn get_my_points(
+  Guard(claims): Guard,
+  ValidatedQuery(query): ValidatedQuery<PointHistoryQuery>,
+  State(state): State<AppState>,
+) -> Result<Json<MyPoints>, AppError> {
+  let prisma_client = state.prisma_client;
+  let page = query.page.unwrap_or(1);
+  let limit = query.limit.unwrap_or(20);
+
+  #[derive(Deserialize)]
+  struct Totals {
+    total: i32,
+    weekly: i32,
+    monthly: i32,
+  }
+
+  let now = Utc::now();
+  let week_start: DateTime<FixedOffset> = LeaderboardPeriod::Weekly.start(now).unwrap().into();
+  let month_start: DateTime<FixedOffset> = LeaderboardPeriod::Monthly.start(now).unwrap().into();
+
+  let (totals, history) = tokio::join!(
+    prisma_client
+      ._query_raw::<Totals>(raw!(
+        r#"
+        SELECT
+          CAST(COALESCE(SUM("point"), 0) AS INTEGER) AS "total",
+          CAST(COALESCE(SUM("point") FILTER (WHERE "created_at" >= {}), 0) AS INTEGER) AS "weekly",
+          CAST(COALESCE(SUM("point") FILTER (WHERE "created_at" >= {}), 0) AS INTEGER) AS "monthly"
+        FROM "activity"
+        WHERE "user_id" = {}
+        "#,
+        PrismaValue::DateTime(week_start),
+        PrismaValue::DateTime(month_start),
+        PrismaValue::Int(claims.id as i64)
+      ))
+      .exec()
+      .observe("query_raw.get_my_points"),
+    prisma_client
+      .activity()
+      .find_many(vec![activity::user_id::equals(claims.id)])
+      .order_by(activity::created_at::order(Direction::Desc))
+      .skip((page - 1) * limit)
+      .take(limit)
+      .select(point_history::select())
+      .exec()
+      .observe("activity.find_many")
+  );
+
+  let Totals {
+    total,
+    weekly,
+    monthly,
+  } = totals?.into_iter().next().unwrap_or(Totals {
+    total: 0,
+    weekly: 0,
+    monthly: 0,
+  });
+
+  Ok(Json(MyPoints {
+    total,
+    weekly,
+    monthly,
+    history: history?,
+  }))
+}
+
+#[derive(Deserialize, IntoParams, Validate)]
+#[into_params(parameter_in = Query)]
+pub struct LeaderboardQuery {
+  period: Option<LeaderboardPeriod>,
+
+  #[validate(range(min = 1, max = 100))]
+  limit: Option<isize>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeaderboardEntry {
+  rank: usize,
+  points: i64,
+  user: leaderboard_user::Data,
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  get,
+  params(
+    LeaderboardQuery
+  ),
+  path = "/leaderboard",
+  tag = "point",
+  responses(
+      (status = 200, description = "return top users by points for the period")
+  )
+)]
+pub async fn get_leaderboard(
+  ValidatedQuery(query): ValidatedQuery<LeaderboardQuery>,
+  State(state): State<AppState>,
+) -> Result<Json<Vec<LeaderboardEntry>>, AppError> {
+  let AppState {
+    prisma_client,
+    mut redis_conn,
+    ..
+  } = state;
+  let period = query.period.unwrap_or_default();
+  let limit = query.limit.unwrap_or(20);
+
+  let now = Utc::now();
+  let key = period.redis_key(now);
+
+  let exists: bool = redis::cmd("EXISTS")
+    .arg(&key)
+    .query_async(&mut redis_conn)
+    .await?;
+
+  if !exists {
+    rebuild_leaderboard(&prisma_client, &mut redis_conn, period, now).await?;
+  }
+
+  let ranking: Vec<(i32, f64)> = redis::cmd("ZREVRANGE")
+    .arg(&key)
+    .arg(0)
+    .arg(limit - 1)
+    .arg("WITHSCORES")
+    .query_async(&mut redis_conn)
+    .await?;
+
+  let users = prisma_client
+    .user()
+    .find_many(vec![prisma::user::id::in_vec(
+      ranking.iter().map(|(user_id, _)| *user_id).collect(),
+    )])
+    .select(leaderboard_user::select())
+    .exec()
+    .observe("user.find_many")
+    .await?;
+
+  let entries = ranking
+    .into_iter()
+    .enumerate()
+    .filter_map(|(index, (user_id, points))| {
+      users
+        .iter()
+        .find(|u| u.id == user_id)
+        .map(|user| LeaderboardEntry {
+          rank: index + 1,
+          points: points as i64,
+          user: user.clone(),
+        })
+    })
+    .collect();
+
+  Ok(Json(entries))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn every_user_earns_a_review_activity() {
+    assert_eq!(
+      idempotency_key(ActivityKind::Reacthelpful, &ActivitySource::Review(7), 1),
+      idempotency_key(ActivityKind::Reacthelpful, &ActivitySource::Review(7), 1),
+    );
+    assert_ne!(
+      idempotency_key(ActivityKind::Reacthelpful, &ActivitySource::Review(7), 1),
+      idempotency_key(ActivityKind::Reacthelpful, &ActivitySource::Review(7), 2),
+    );
+    assert_ne!(
+      idempotency_key(ActivityKind::Reacthelpful, &ActivitySource::Review(7), 1),
+      idempotency_key(ActivityKind::Reply, &ActivitySource::Review(7), 1),
+    );
+  }
+
+  #[test]
+  fn join_activity_keys_name_the_platform_account() {
+    let discord = |id: &str| ActivitySource::Platform(id.to_string());
+
+    assert_eq!(
+      idempotency_key(ActivityKind::JoinDiscord, &discord("80351110224678912"), 1),
+      "join_discord:platform:80351110224678912:user:1",
+    );
+    assert_ne!(
+      idempotency_key(ActivityKind::JoinDiscord, &discord("80351110224678912"), 1),
+      idempotency_key(ActivityKind::JoinDiscord, &discord("41771983423143937"), 1),
+    );
+    assert_ne!(
+      idempotency_key(ActivityKind::JoinDiscord, &discord("1"), 1),
+      idempotency_key(ActivityKind::JoinTelegram, &discord("1"), 1),
+    );
+  }
+}
@@ -0,0 +1,610 @@
+use crate::config::Config;
+use crate::database::observe::ObserveQuery;
+use crate::database::prisma::{self, social, ActivityKind, PrismaClient};
+use crate::database::redis_conn::RedisConn;
+use crate::{
+  intercept::{sercurity::Guard, validate::ValidatedJson},
+  services::point::{record_activity, ActivitySource},
+  utils::random_string,
+  AppState,
+};
+use anyhow::{anyhow, bail, Result};
+use axum::{
+  extract::{Path, State},
+  http::StatusCode,
+  Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use error::AppError;
+use hmac::{Hmac, Mac};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+use validator::Validate;
+
+const OAUTH_STATE_TTL_SECONDS: usize = 10 * 60;
+const TELEGRAM_AUTH_MAX_AGE_SECONDS: i64 = 24 * 60 * 60;
+
+#[derive(Deserialize, Serialize, ToSchema, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SocialPlatform {
+  Twitter,
+  Discord,
+  Telegram,
+}
+
+struct OAuthProvider {
+  client_id: String,
+  client_secret: Option<String>,
+  redirect_uri: String,
+  authorize_url: String,
+  token_url: String,
+  profile_url: String,
+  scope: &'static str,
+}
+
+impl OAuthProvider {
+  fn from_config(config: &Config, platform: SocialPlatform) -> Result<Self> {
+    let (client, authorize_url, token_url, profile_url, scope) = match platform {
+      SocialPlatform::Twitter => (
+        &config.twitter,
+        "https://twitter.com/i/oauth2/authorize",
+        "https://api.twitter.com/2/oauth2/token",
+        "https://api.twitter.com/2/users/me",
+        "users.read tweet.read",
+      ),
+      SocialPlatform::Discord => (
+        &config.discord,
+        "https://discord.com/oauth2/authorize",
+        "https://discord.com/api/oauth2/token",
+        "https://discord.com/api/users/@me",
+        "identify",
+      ),
+      SocialPlatform::Telegram => bail!("Telegram does not use OAuth2"),
+    };
+
+    let client = client
+      .as_ref()
+      .ok_or_else(|| anyhow!("Linking this platform is not configured"))?;
+    let or_default =
+      |url: &Option<String>, default: &str| url.to_owned().unwrap_or_else(|| default.to_string());
+
+    Ok(Self {
+      client_id: client.client_id.to_owned(),
+      client_secret: client.client_secret.to_owned(),
+      redirect_uri: client.redirect_uri.to_owned(),
+      authorize_url: or_default(&client.authorize_url, authorize_url),
+      token_url: or_default(&client.token_url, token_url),
+      profile_url: or_default(&client.profile_url, profile_url),
+      scope,
+    })
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+struct OAuthState {
+  user_id: i32,
+  code_verifier: String,
+}
+
+#[derive(Serialize)]
+struct TokenRequest<'a> {
+  grant_type: &'static str,
+  code: &'a str,
+  redirect_uri: &'a str,
+  client_id: &'a str,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  client_secret: Option<&'a str>,
+  code_verifier: &'a str,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+  access_token: String,
+}
+
+#[derive(Deserialize)]
+struct TwitterProfile {
+  data: TwitterUser,
+}
+
+#[derive(Deserialize)]
+struct TwitterUser {
+  id: String,
+  username: String,
+}
+
+#[derive(Deserialize)]
+struct DiscordProfile {
+  id: String,
+  username: String,
+}
+
+struct LinkedAccount {
+  id: String,
+  name: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AuthorizeUrl {
+  url: String,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct OAuthCallbackPayload {
+  #[validate(length(min = 1))]
+  code: String,
+
+  #[validate(length(min = 1))]
+  state: String,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Validate)]
+pub struct TelegramAuthPayload {
+  id: i64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  first_name: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  last_name: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  username: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  photo_url: Option<String>,
+  auth_date: i64,
+  #[serde(skip_serializing)]
+  hash: String,
+}
+
+// The provider refused the request, e.g. an expired code, as opposed to being unreachable or broken.
+#[derive(Debug)]
+struct ProviderRejected(surf::StatusCode);
+
+impl std::fmt::Display for ProviderRejected {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "Provider rejected the request with status {}", self.0)
+  }
+}
+
+impl std::error::Error for ProviderRejected {}
+
+async fn fetch_json<T: DeserializeOwned>(request: surf::RequestBuilder) -> Result<T> {
+  let mut response = request.await.map_err(surf::Error::into_inner)?;
+
+  if response.status().is_client_error() {
+    bail!(ProviderRejected(response.status()));
+  }
+
+  if !response.status().is_success() {
+    bail!("Provider responded with status {}", response.status());
+  }
+
+  response
+    .body_json::<T>()
+    .await
+    .map_err(surf::Error::into_inner)
+}
+
+async fn fetch_linked_account(
+  platform: SocialPlatform,
+  provider: &OAuthProvider,
+  code: &str,
+  code_verifier: &str,
+) -> Result<LinkedAccount> {
+  let token_request = TokenRequest {
+    grant_type: "authorization_code",
+    code,
+    redirect_uri: &provider.redirect_uri,
+    client_id: &provider.client_id,
+    client_secret: provider.client_secret.as_deref(),
+    code_verifier,
+  };
+
+  let token = fetch_json::<TokenResponse>(
+    surf::post(&provider.token_url)
+      .body(surf::Body::from_form(&token_request).map_err(surf::Error::into_inner)?),
+  )
+  .await?;
+
+  let profile_request = surf::get(&provider.profile_url).header(
+    "Authorization",
+    format!("Bearer {}", token.access_token),
+  );
+
+  match platform {
+    SocialPlatform::Twitter => {
+      let TwitterProfile { data } = fetch_json(profile_request).await?;
+      Ok(LinkedAccount {
+        id: data.id,
+        name: data.username,
+      })
+    }
+    SocialPlatform::Discord => {
+      let profile: DiscordProfile = fetch_json(profile_request).await?;
+      Ok(LinkedAccount {
+        id: profile.id,
+        name: profile.username,
+      })
+    }
+    SocialPlatform::Telegram => Err(anyhow!("Telegram does not use OAuth2")),
+  }
+}
+
+// RFC 7636 `S256`, the provider only ever sees the hash of the verifier kept in Redis.
+fn pkce_challenge(code_verifier: &str) -> String {
+  URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+pub fn verify_telegram_auth(
+  payload: &TelegramAuthPayload,
+  bot_token: &str,
+  now: i64,
+) -> Result<()> {
+  if now - payload.auth_date > TELEGRAM_AUTH_MAX_AGE_SECONDS {
+    bail!("Telegram authorization is outdated");
+  }
+
+  let fields: BTreeMap<String, serde_json::Value> =
+    serde_json::from_value(serde_json::to_value(payload)?)?;
+
+  let data_check_string = fields
+    .into_iter()
+    .map(|(key, value)| match value {
+      serde_json::Value::String(value) => format!("{key}={value}"),
+      value => format!("{key}={value}"),
+    })
+    .collect::<Vec<String>>()
+    .join("\n");
+
+  let secret_key = Sha256::digest(bot_token.as_bytes());
+  let mut mac =
+    Hmac::<Sha256>::new_from_slice(&secret_key).expect("HMAC can take key of any size");
+  mac.update(data_check_string.as_bytes());
+
+  mac
+    .verify_slice(&hex::decode(&payload.hash)?)
+    .map_err(|_| anyhow!("Telegram authorization hash mismatch"))
+}
+
+async fn link_account(
+  prisma_client: &PrismaClient,
+  redis_conn: &mut RedisConn,
+  user_id: i32,
+  platform: SocialPlatform,
+  account: LinkedAccount,
+) -> Result<social::Data, AppError> {
+  let link_params = || {
+    let id = Some(account.id.to_owned());
+    let name = Some(account.name.to_owned());
+    let mut params = match platform {
+      SocialPlatform::Twitter => vec![social::twitter_id::set(id), social::twitter::set(name)],
+      SocialPlatform::Discord => vec![social::discord_id::set(id), social::discord::set(name)],
+      SocialPlatform::Telegram => vec![social::telegram_id::set(id), social::telegram::set(name)],
+    };
+    params.push(social::last_update::set(Some(Utc::now().into())));
+    params
+  };
+
+  // Platform ids are unique, an account linked to another user fails the upsert with a conflict.
+  let social = prisma_client
+    .social()
+    .upsert(
+      social::user_id::equals(user_id),
+      social::create(prisma::user::id::equals(user_id), link_params()),
+      link_params(),
+    )
+    .exec()
+    .observe("social.upsert")
+    .await?;
+
+  let activity_kind = match platform {
+    SocialPlatform::Discord => Some(ActivityKind::JoinDiscord),
+    SocialPlatform::Telegram => Some(ActivityKind::JoinTelegram),
+    SocialPlatform::Twitter => None,
+  };
+
+  if let Some(kind) = activity_kind {
+    record_activity(
+      prisma_client,
+      redis_conn,
+      user_id,
+      kind,
+      ActivitySource::Platform(account.id),
+    )
+    .await?;
+  }
+
+  Ok(social)
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  get,
+  path = "/socials/{platform}/authorize",
+  tag = "social",
+  params(
+    ("platform" = SocialPlatform, Path, description = "twitter or discord")
+  ),
+  responses(
+      (status = 200, description = "return the provider authorization url to redirect to", body = AuthorizeUrl)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn authorize_social(
+  Guard(claims): Guard,
+  Path(platform): Path<SocialPlatform>,
+  State(state): State<AppState>,
+) -> Result<Json<AuthorizeUrl>, AppError> {
+  if platform == SocialPlatform::Telegram {
+    return Err(AppError::bad_request(
+      "Telegram is linked through the login widget",
+    ));
+  }
+
+  let provider = OAuthProvider::from_config(&state.config, platform)?;
+  let mut redis_conn = state.redis_conn;
+  let oauth_state = random_string(32);
+  let code_verifier = random_string(64);
+  let code_challenge = pkce_challenge(&code_verifier);
+
+  redis::cmd("SET")
+    .arg(format!("oauth_state:{oauth_state}"))
+    .arg(serde_json::to_string(&OAuthState {
+      user_id: claims.id,
+      code_verifier: code_verifier.to_owned(),
+    })?)
+    .arg("EX")
+    .arg(OAUTH_STATE_TTL_SECONDS)
+    .query_async::<_, ()>(&mut redis_conn)
+    .await?;
+
+  let url = surf::Url::parse_with_params(
+    &provider.authorize_url,
+    &[
+      ("response_type", "code"),
+      ("client_id", provider.client_id.as_str()),
+      ("redirect_uri", provider.redirect_uri.as_str()),
+      ("scope", provider.scope),
+      ("state", oauth_state.as_str()),
+      ("code_challenge", code_challenge.as_str()),
+      ("code_challenge_method", "S256"),
+    ],
+  )?;
+
+  Ok(Json(AuthorizeUrl {
+    url: url.to_string(),
+  }))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  post,
+  path = "/socials/{platform}/callback",
+  tag = "social",
+  params(
+    ("platform" = SocialPlatform, Path, description = "twitter or discord")
+  ),
+  request_body = OAuthCallbackPayload,
+  responses(
+      (status = 200, description = "account was linked, return your socials"),
+      (status = 400, description = "state is unknown or the provider rejected the code", body = crate::open_api::schemas::ErrorBody),
+      (status = 409, description = "account is already linked to another user", body = crate::open_api::schemas::ErrorBody),
+      (status = 503, description = "the provider is unavailable", body = crate::open_api::schemas::ErrorBody)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn link_social(
+  Guard(claims): Guard,
+  Path(platform): Path<SocialPlatform>,
+  State(state): State<AppState>,
+  ValidatedJson(payload): ValidatedJson<OAuthCallbackPayload>,
+) -> Result<Json<social::Data>, AppError> {
+  let AppState {
+    prisma_client,
+    mut redis_conn,
+    config,
+    ..
+  } = state;
+  let provider = OAuthProvider::from_config(&config, platform)?;
+
+  let oauth_state: Option<String> = redis::cmd("GETDEL")
+    .arg(format!("oauth_state:{}", payload.state))
+    .query_async(&mut redis_conn)
+    .await?;
+
+  let oauth_state = match oauth_state {
+    Some(oauth_state) => serde_json::from_str::<OAuthState>(&oauth_state)?,
+    None => return Err(AppError::bad_request("Unknown or expired state")),
+  };
+
+  if oauth_state.user_id != claims.id {
+    return Err(AppError::bad_request("Unknown or expired state"));
+  }
+
+  let account = fetch_linked_account(
+    platform,
+    &provider,
+    &payload.code,
+    &oauth_state.code_verifier,
+  )
+  .await
+  .map_err(|err| {
+    tracing::warn!(user_id = claims.id, error = %err, "fetching linked account failed");
+
+    if err.is::<ProviderRejected>() {
+      AppError::bad_request("The provider rejected the authorization code")
+    } else {
+      AppError::new(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "The provider is unavailable, try again later",
+      )
+      .with_code("provider_unavailable")
+    }
+  })?;
+
+  let social = link_account(
+    &prisma_client,
+    &mut redis_conn,
+    claims.id,
+    platform,
+    account,
+  )
+  .await?;
+
+  Ok(Json(social))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  post,
+  path = "/socials/telegram",
+  tag = "social",
+  request_body = TelegramAuthPayload,
+  responses(
+      (status = 200, description = "account was linked, return your socials"),
+      (status = 400, description = "telegram authorization is invalid or outdated", body = crate::open_api::schemas::ErrorBody),
+      (status = 409, description = "account is already linked to another user", body = crate::open_api::schemas::ErrorBody)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn link_telegram(
+  Guard(claims): Guard,
+  State(state): State<AppState>,
+  ValidatedJson(payload): ValidatedJson<TelegramAuthPayload>,
+) -> Result<Json<social::Data>, AppError> {
+  let AppState {
+    prisma_client,
+    mut redis_conn,
+    config,
+    ..
+  } = state;
+  let bot_token = config
+    .telegram_bot_token
+    .as_deref()
+    .ok_or_else(|| anyhow!("TELEGRAM_BOT_TOKEN is not set"))?;
+
+  verify_telegram_auth(&payload, bot_token, Utc::now().timestamp()).map_err(|err| {
+    tracing::warn!(user_id = claims.id, error = %err, "verifying telegram authorization failed");
+    AppError::bad_request("Telegram authorization is invalid or outdated")
+  })?;
+
+  let account = LinkedAccount {
+    id: payload.id.to_string(),
+    name: payload
+      .username
+      .or(payload.first_name)
+      .unwrap_or_else(|| payload.id.to_string()),
+  };
+
+  let social = link_account(
+    &prisma_client,
+    &mut redis_conn,
+    claims.id,
+    SocialPlatform::Telegram,
+    account,
+  )
+  .await?;
+
+  Ok(Json(social))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  delete,
+  path = "/socials/{platform}",
+  tag = "social",
+  params(
+    ("platform" = SocialPlatform, Path, description = "twitter, discord or telegram")
+  ),
+  responses(
+      (status = 200, description = "account was unlinked, return your socials")
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn unlink_social(
+  Guard(claims): Guard,
+  Path(platform): Path<SocialPlatform>,
+  State(state): State<AppState>,
+) -> Result<Json<social::Data>, AppError> {
+  let mut params = match platform {
+    SocialPlatform::Twitter => vec![social::twitter_id::set(None), social::twitter::set(None)],
+    SocialPlatform::Discord => vec![social::discord_id::set(None), social::discord::set(None)],
+    SocialPlatform::Telegram => vec![
+      social::telegram_id::set(None),
+      social::telegram::set(None),
+    ],
+  };
+  params.push(social::last_update::set(Some(Utc::now().into())));
+
+  let social = state
+    .prisma_client
+    .social()
+    .update(social::user_id::equals(claims.id), params)
+    .exec()
+    .observe("social.update")
+    .await?;
+
+  Ok(Json(social))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const BOT_TOKEN: &str = "123456:ABC-DEF1234ghIkl-zyx57W2v1u123ew11";
+  const AUTH_DATE: i64 = 1_700_000_000;
+
+  // Signed with `BOT_TOKEN` as described in https://core.telegram.org/widgets/login#checking-authorization
+  fn telegram_payload() -> TelegramAuthPayload {
+    TelegramAuthPayload {
+      id: 424242,
+      first_name: Some("Alice".to_string()),
+      last_name: None,
+      username: Some("alice".to_string()),
+      photo_url: None,
+      auth_date: AUTH_DATE,
+      hash: "8b3fb9af71d7e299e690ec31bb2c1a1c7aad8359218ac99dafa707cfb09bd35a".to_string(),
+    }
+  }
+
+  #[test]
+  fn accepts_a_valid_telegram_hash() {
+    assert!(verify_telegram_auth(&telegram_payload(), BOT_TOKEN, AUTH_DATE + 60).is_ok());
+  }
+
+  #[test]
+  fn rejects_a_tampered_telegram_field() {
+    let payload = TelegramAuthPayload {
+      username: Some("mallory".to_string()),
+      ..telegram_payload()
+    };
+
+    assert!(verify_telegram_auth(&payload, BOT_TOKEN, AUTH_DATE + 60).is_err());
+    assert!(verify_telegram_auth(&telegram_payload(), "654321:other", AUTH_DATE + 60).is_err());
+  }
+
+  #[test]
+  fn rejects_a_stale_telegram_auth_date() {
+    let now = AUTH_DATE + TELEGRAM_AUTH_MAX_AGE_SECONDS + 1;
+
+    assert!(verify_telegram_auth(&telegram_payload(), BOT_TOKEN, now).is_err());
+  }
+
+  #[test]
+  fn pkce_challenge_is_s256() {
+    // RFC 7636, appendix B.
+    assert_eq!(
+      pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+      "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+    );
+  }
+}
@@ -1,7 +1,22 @@
+use crate::database::observe::ObserveQuery;
 use crate::AppState;
-use crate::{database::prisma, intercept::sercurity::Guard};
-use axum::{extract::State, Json};
+use crate::{
+  database::prisma,
+  intercept::{sercurity::Guard, validate::ValidatedJson},
+  storage::image_extension,
+  utils::random_string,
+};
+use axum::{
+  extract::{Multipart, State},
+  Json,
+};
+use chrono::Utc;
 use error::AppError;
+use serde::Deserialize;
+use utoipa::ToSchema;
+use validator::Validate;
+
+pub const MAX_IMAGE_BYTES: usize = 5 * 1024 * 1024;
 
 prisma::user::select!(me {
   id
@@ -12,8 +27,17 @@ prisma::user::select!(me {
   last_sync_ibt
   last_update
   nickname
-  did: include {
-    users
+  did: select {
+    id
+    controller
+    email
+    username
+    users: select {
+      id
+      wallet_address
+      nickname
+      avatar_url
+    }
   }
 });
 
@@ -23,7 +47,8 @@ prisma::user::select!(me {
   path = "/users",
   tag = "user",
   responses(
-      (status = 200, description = "return your information")
+      (status = 200, description = "return your information", body = crate::open_api::schemas::Me),
+      (status = 404, description = "your account no longer exists", body = crate::open_api::schemas::ErrorBody)
   ),
   security(
     ("BearerAuth" = []),
@@ -40,8 +65,167 @@ pub async fn who_am_i(
     .find_unique(prisma::user::id::equals(claims.id))
     .select(me::select())
     .exec()
+    .observe("user.find_unique")
     .await?
-    .unwrap();
+    .ok_or_else(|| AppError::not_found("User not found"))?;
+
+  Ok(Json(me))
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+pub struct UpdateProfilePayload {
+  #[validate(length(min = 3, max = 32))]
+  nickname: Option<String>,
+
+  #[validate(email)]
+  email: Option<String>,
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  patch,
+  path = "/users/me",
+  tag = "user",
+  request_body = UpdateProfilePayload,
+  responses(
+      (status = 200, description = "return your updated information", body = crate::open_api::schemas::Me),
+      (status = 409, description = "nickname is already taken", body = crate::open_api::schemas::ErrorBody)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn update_me(
+  Guard(claims): Guard,
+  State(state): State<AppState>,
+  ValidatedJson(payload): ValidatedJson<UpdateProfilePayload>,
+) -> Result<Json<me::Data>, AppError> {
+  let prisma_client = state.prisma_client;
+  let UpdateProfilePayload { nickname, email } = payload;
+
+  let mut params = vec![prisma::user::last_update::set(Some(Utc::now().into()))];
+
+  // A nickname taken by someone else violates its unique index, which answers 409.
+  if let Some(nickname) = nickname {
+    params.push(prisma::user::nickname::set(Some(nickname)));
+  }
+
+  if email.is_some() {
+    params.push(prisma::user::email::set(email));
+  }
+
+  let me = prisma_client
+    .user()
+    .update(prisma::user::id::equals(claims.id), params)
+    .select(me::select())
+    .exec()
+    .observe("user.update")
+    .await?;
+
+  Ok(Json(me))
+}
+
+async fn store_profile_image(
+  state: &AppState,
+  user_id: i32,
+  folder: &str,
+  mut multipart: Multipart,
+) -> Result<String, AppError> {
+  while let Some(field) = multipart.next_field().await? {
+    if field.name() != Some("file") {
+      continue;
+    }
+
+    let content_type = field.content_type().unwrap_or_default().to_string();
+    let extension = image_extension(&content_type)
+      .ok_or_else(|| AppError::bad_request("Only png, jpeg, webp or gif images are allowed"))?;
+
+    let bytes = field.bytes().await?;
+    let key = format!("{folder}/{user_id}/{}.{extension}", random_string(16));
+
+    return Ok(
+      state
+        .storage
+        .put(&key, &content_type, bytes.to_vec())
+        .await?,
+    );
+  }
+
+  Err(AppError::bad_request("Missing file field"))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  put,
+  path = "/users/me/avatar",
+  tag = "user",
+  request_body(content = Vec<u8>, content_type = "multipart/form-data", description = "image in the `file` field"),
+  responses(
+      (status = 200, description = "return your updated information", body = crate::open_api::schemas::Me)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn upload_avatar(
+  Guard(claims): Guard,
+  State(state): State<AppState>,
+  multipart: Multipart,
+) -> Result<Json<me::Data>, AppError> {
+  let url = store_profile_image(&state, claims.id, "avatars", multipart).await?;
+
+  let me = state
+    .prisma_client
+    .user()
+    .update(
+      prisma::user::id::equals(claims.id),
+      vec![
+        prisma::user::avatar_url::set(Some(url)),
+        prisma::user::last_update::set(Some(Utc::now().into())),
+      ],
+    )
+    .select(me::select())
+    .exec()
+    .observe("user.update")
+    .await?;
+
+  Ok(Json(me))
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  put,
+  path = "/users/me/background",
+  tag = "user",
+  request_body(content = Vec<u8>, content_type = "multipart/form-data", description = "image in the `file` field"),
+  responses(
+      (status = 200, description = "return your updated information", body = crate::open_api::schemas::Me)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn upload_background(
+  Guard(claims): Guard,
+  State(state): State<AppState>,
+  multipart: Multipart,
+) -> Result<Json<me::Data>, AppError> {
+  let url = store_profile_image(&state, claims.id, "backgrounds", multipart).await?;
+
+  let me = state
+    .prisma_client
+    .user()
+    .update(
+      prisma::user::id::equals(claims.id),
+      vec![
+        prisma::user::background_url::set(Some(url)),
+        prisma::user::last_update::set(Some(Utc::now().into())),
+      ],
+    )
+    .select(me::select())
+    .exec()
+    .observe("user.update")
+    .await?;
 
   Ok(Json(me))
 }
@@ -0,0 +1,457 @@
+use crate::config::{Config, VoucherConfig};
+use crate::database::observe::ObserveQuery;
+use crate::database::prisma::user_campaign;
+use crate::database::redis_conn::RedisConn;
+use crate::{intercept::sercurity::Guard, AppState};
+use anyhow::{anyhow, Result};
+use axum::{
+  extract::{Path, State},
+  http::StatusCode,
+  Json,
+};
+use chrono::Utc;
+use error::AppError;
+use ethers::{
+  contract::{parse_log, EthEvent},
+  providers::{Http, Middleware, Provider},
+  signers::{LocalWallet, Signer},
+  types::{transaction::eip712::TypedData, Address, Log, Signature, H256, U256},
+};
+use serde::Serialize;
+use serde_json::json;
+
+const VOUCHER_DOMAIN_NAME: &str = "AxumBabyRewards";
+const VOUCHER_DOMAIN_VERSION: &str = "1";
+const VOUCHER_TTL_SECONDS: i64 = 60 * 60;
+
+pub struct VoucherDomain {
+  pub chain_id: u64,
+  pub verifying_contract: Address,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Voucher {
+  pub user: Address,
+  pub campaign_id: i32,
+  pub amount: i32,
+  pub nonce: u64,
+  pub deadline: i64,
+}
+
+// Emitted by the reward contract once a voucher is redeemed.
+#[derive(Clone, Debug, PartialEq, Eq, EthEvent)]
+#[ethevent(
+  name = "RewardClaimed",
+  abi = "RewardClaimed(address,uint256,uint256,uint256)"
+)]
+pub struct RewardClaimed {
+  #[ethevent(indexed)]
+  pub user: Address,
+  #[ethevent(indexed)]
+  pub campaign_id: U256,
+  pub amount: U256,
+  pub nonce: U256,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedVoucher {
+  typed_data: TypedData,
+  signature: String,
+}
+
+impl VoucherDomain {
+  pub fn new(config: &VoucherConfig) -> Self {
+    Self {
+      chain_id: config.chain_id,
+      verifying_contract: config.contract,
+    }
+  }
+}
+
+// Rewards are optional, without a reward contract there is nothing to sign or claim.
+pub fn voucher_config(config: &Config) -> Result<&VoucherConfig, AppError> {
+  config.voucher.as_ref().ok_or_else(|| {
+    AppError::new(StatusCode::SERVICE_UNAVAILABLE, "Rewards are not enabled")
+      .with_code("rewards_disabled")
+  })
+}
+
+pub fn voucher_typed_data(domain: &VoucherDomain, voucher: &Voucher) -> Result<TypedData> {
+  let typed_data = serde_json::from_value(json!({
+    "types": {
+      "EIP712Domain": [
+        { "name": "name", "type": "string" },
+        { "name": "version", "type": "string" },
+        { "name": "chainId", "type": "uint256" },
+        { "name": "verifyingContract", "type": "address" }
+      ],
+      "RewardVoucher": [
+        { "name": "user", "type": "address" },
+        { "name": "campaignId", "type": "uint256" },
+        { "name": "amount", "type": "uint256" },
+        { "name": "nonce", "type": "uint256" },
+        { "name": "deadline", "type": "uint256" }
+      ]
+    },
+    "primaryType": "RewardVoucher",
+    "domain": {
+      "name": VOUCHER_DOMAIN_NAME,
+      "version": VOUCHER_DOMAIN_VERSION,
+      "chainId": domain.chain_id,
+      "verifyingContract": domain.verifying_contract
+    },
+    "message": voucher
+  }))?;
+
+  Ok(typed_data)
+}
+
+pub async fn sign_voucher(wallet: &LocalWallet, typed_data: &TypedData) -> Result<Signature> {
+  Ok(wallet.sign_typed_data(typed_data).await?)
+}
+
+fn issued_voucher_key(user_id: i32, campaign_id: i32) -> String {
+  format!("issued_voucher:{user_id}:{campaign_id}")
+}
+
+// The nonce and amount of the first voucher issued for an allocation. Every later voucher repeats
+// both, so that a voucher still held by the user redeems exactly what the others would.
+#[derive(Debug, PartialEq, Eq)]
+pub struct IssuedVoucher {
+  pub nonce: u64,
+  pub amount: i32,
+}
+
+impl IssuedVoucher {
+  fn encode(&self) -> String {
+    format!("{}:{}", self.nonce, self.amount)
+  }
+
+  fn decode(value: &str) -> Result<Self> {
+    let (nonce, amount) = value
+      .split_once(':')
+      .ok_or_else(|| anyhow!("malformed issued voucher {value}"))?;
+
+    Ok(Self {
+      nonce: nonce.parse()?,
+      amount: amount.parse()?,
+    })
+  }
+}
+
+// The voucher issued for this allocation, if any.
+pub async fn issued_voucher(
+  redis_conn: &mut RedisConn,
+  user_id: i32,
+  campaign_id: i32,
+) -> Result<Option<IssuedVoucher>> {
+  redis::cmd("GET")
+    .arg(issued_voucher_key(user_id, campaign_id))
+    .query_async::<_, Option<String>>(redis_conn)
+    .await?
+    .map(|value| IssuedVoucher::decode(&value))
+    .transpose()
+}
+
+// Users of `user_ids` holding a voucher for this campaign, their allocation is fixed from then on.
+pub async fn users_with_issued_vouchers(
+  redis_conn: &mut RedisConn,
+  campaign_id: i32,
+  user_ids: &[i32],
+) -> Result<Vec<i32>> {
+  if user_ids.is_empty() {
+    return Ok(vec![]);
+  }
+
+  let issued = redis::cmd("MGET")
+    .arg(
+      user_ids
+        .iter()
+        .map(|user_id| issued_voucher_key(*user_id, campaign_id))
+        .collect::<Vec<String>>(),
+    )
+    .query_async::<_, Vec<Option<String>>>(redis_conn)
+    .await?;
+
+  Ok(
+    user_ids
+      .iter()
+      .zip(issued)
+      .filter(|(_, issued)| issued.is_some())
+      .map(|(user_id, _)| *user_id)
+      .collect(),
+  )
+}
+
+// Records a voucher for `amount` under a fresh nonce, unless one was issued already, and returns
+// whichever voucher is on record.
+#[tracing::instrument(skip(redis_conn))]
+async fn issue_voucher(
+  redis_conn: &mut RedisConn,
+  user_id: i32,
+  campaign_id: i32,
+  amount: i32,
+) -> Result<IssuedVoucher> {
+  if let Some(issued) = issued_voucher(redis_conn, user_id, campaign_id).await? {
+    return Ok(issued);
+  }
+
+  let nonce: u64 = redis::cmd("INCR")
+    .arg("voucher_nonce")
+    .query_async(redis_conn)
+    .await?;
+
+  redis::cmd("SET")
+    .arg(issued_voucher_key(user_id, campaign_id))
+    .arg(IssuedVoucher { nonce, amount }.encode())
+    .arg("NX")
+    .query_async::<_, Option<String>>(redis_conn)
+    .await?;
+
+  issued_voucher(redis_conn, user_id, campaign_id)
+    .await?
+    .ok_or_else(|| anyhow!("issued voucher disappeared"))
+}
+
+// Whether `logs` contain a redemption of exactly `expected` by the reward contract.
+fn redeems_voucher(logs: &[Log], contract: Address, expected: &Voucher) -> bool {
+  logs
+    .iter()
+    .filter(|log| log.address == contract)
+    .filter_map(|log| parse_log::<RewardClaimed>(log.clone()).ok())
+    .any(|claimed| {
+      claimed.user == expected.user
+        && claimed.campaign_id == U256::from(expected.campaign_id)
+        && claimed.amount == U256::from(expected.amount)
+        && claimed.nonce == U256::from(expected.nonce)
+    })
+}
+
+// Checks that the submitted transaction was mined successfully by the claimer and redeemed the voucher
+// issued for this allocation, matching its campaign, amount and nonce. The deadline is enforced on chain.
+// Rejections are client errors, RPC failures stay internal.
+pub async fn verify_claim_transaction(
+  config: &VoucherConfig,
+  txn_hash: &str,
+  expected: &Voucher,
+) -> Result<(), AppError> {
+  let provider = Provider::<Http>::try_from(config.rpc_url.as_str())?;
+  let domain = VoucherDomain::new(config);
+  let txn_hash = txn_hash
+    .parse::<H256>()
+    .map_err(|_| AppError::bad_request("Invalid transaction hash"))?;
+
+  let receipt = provider
+    .get_transaction_receipt(txn_hash)
+    .await?
+    .ok_or_else(|| AppError::bad_request("Transaction is not mined yet"))?;
+
+  if receipt.status != Some(1.into()) {
+    return Err(AppError::bad_request("Transaction was reverted"));
+  }
+
+  if receipt.to != Some(domain.verifying_contract) {
+    return Err(AppError::bad_request(
+      "Transaction was not sent to the reward contract",
+    ));
+  }
+
+  if receipt.from != expected.user {
+    return Err(AppError::bad_request(
+      "Transaction was not sent by your wallet",
+    ));
+  }
+
+  if !redeems_voucher(&receipt.logs, domain.verifying_contract, expected) {
+    return Err(AppError::bad_request(
+      "Transaction does not redeem the voucher of this campaign",
+    ));
+  }
+
+  Ok(())
+}
+
+#[axum_macros::debug_handler]
+#[utoipa::path(
+  get,
+  path = "/campaigns/{id}/voucher",
+  tag = "campaign",
+  params(
+    ("id" = i32, Path, description = "campaign id")
+  ),
+  responses(
+      (status = 200, description = "return an EIP-712 voucher signed by the reward operator"),
+      (status = 404, description = "you have no allocation in this campaign", body = crate::open_api::schemas::ErrorBody),
+      (status = 409, description = "reward was already claimed", body = crate::open_api::schemas::ErrorBody),
+      (status = 503, description = "rewards are not enabled", body = crate::open_api::schemas::ErrorBody)
+  ),
+  security(
+    ("BearerAuth" = []),
+  )
+)]
+pub async fn get_voucher(
+  Guard(claims): Guard,
+  Path(campaign_id): Path<i32>,
+  State(state): State<AppState>,
+) -> Result<Json<SignedVoucher>, AppError> {
+  let AppState {
+    prisma_client,
+    mut redis_conn,
+    config,
+    ..
+  } = state;
+  let voucher_config = voucher_config(&config)?;
+
+  let user_campaign = prisma_client
+    .user_campaign()
+    .find_unique(user_campaign::user_id_campaign_id(claims.id, campaign_id))
+    .exec()
+    .observe("user_campaign.find_unique")
+    .await?
+    .ok_or_else(|| AppError::not_found("No allocation for this campaign"))?;
+
+  if user_campaign.claimed {
+    return Err(AppError::conflict("Reward was already claimed"));
+  }
+
+  let issued = issue_voucher(
+    &mut redis_conn,
+    claims.id,
+    campaign_id,
+    user_campaign.amount,
+  )
+  .await?;
+
+  let voucher = Voucher {
+    user: claims.wallet_address.parse::<Address>()?,
+    campaign_id,
+    amount: issued.amount,
+    nonce: issued.nonce,
+    deadline: Utc::now().timestamp() + VOUCHER_TTL_SECONDS,
+  };
+
+  let wallet = voucher_config.signer_key.parse::<LocalWallet>()?;
+  let typed_data = voucher_typed_data(&VoucherDomain::new(voucher_config), &voucher)?;
+  let signature = sign_voucher(&wallet, &typed_data).await?;
+
+  Ok(Json(SignedVoucher {
+    typed_data,
+    signature: format!("0x{signature}"),
+  }))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const OPERATOR_KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+  #[tokio::test]
+  async fn recovers_voucher_signer() {
+    let wallet = OPERATOR_KEY.parse::<LocalWallet>().unwrap();
+    let domain = VoucherDomain {
+      chain_id: 1,
+      verifying_contract: "0x5FbDB2315678afecb367f032d93F642f64180aa3"
+        .parse()
+        .unwrap(),
+    };
+    let voucher = Voucher {
+      user: "0x70997970C51812dc3A010C7d01b50e0d17dc79C8".parse().unwrap(),
+      campaign_id: 7,
+      amount: 1500,
+      nonce: 42,
+      deadline: 1_900_000_000,
+    };
+
+    let typed_data = voucher_typed_data(&domain, &voucher).unwrap();
+    let signature = sign_voucher(&wallet, &typed_data).await.unwrap();
+
+    assert_eq!(
+      signature.recover_typed_data(&typed_data).unwrap(),
+      wallet.address()
+    );
+
+    let tampered = voucher_typed_data(
+      &domain,
+      &Voucher {
+        amount: 15000,
+        ..voucher
+      },
+    )
+    .unwrap();
+
+    assert_ne!(
+      signature.recover_typed_data(&tampered).unwrap(),
+      wallet.address()
+    );
+  }
+
+  fn claimed_log(contract: Address, voucher: &Voucher) -> Log {
+    Log {
+      address: contract,
+      topics: vec![
+        RewardClaimed::signature(),
+        H256::from(voucher.user),
+        H256::from_low_u64_be(voucher.campaign_id as u64),
+      ],
+      data: ethers::abi::encode(&[
+        ethers::abi::Token::Uint(U256::from(voucher.amount)),
+        ethers::abi::Token::Uint(U256::from(voucher.nonce)),
+      ])
+      .into(),
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn only_the_issued_voucher_is_accepted() {
+    let contract: Address = "0x5FbDB2315678afecb367f032d93F642f64180aa3"
+      .parse()
+      .unwrap();
+    let voucher = Voucher {
+      user: "0x70997970C51812dc3A010C7d01b50e0d17dc79C8".parse().unwrap(),
+      campaign_id: 7,
+      amount: 1500,
+      nonce: 42,
+      deadline: 0,
+    };
+
+    let logs = vec![claimed_log(contract, &voucher)];
+    assert!(redeems_voucher(&logs, contract, &voucher));
+
+    for other in [
+      Voucher {
+        campaign_id: 8,
+        ..voucher.clone()
+      },
+      Voucher {
+        amount: 15000,
+        ..voucher.clone()
+      },
+      Voucher {
+        nonce: 43,
+        ..voucher.clone()
+      },
+    ] {
+      assert!(!redeems_voucher(&logs, contract, &other));
+    }
+
+    // The same event emitted by another contract proves nothing.
+    let elsewhere = vec![claimed_log(Address::zero(), &voucher)];
+    assert!(!redeems_voucher(&elsewhere, contract, &voucher));
+  }
+
+  #[test]
+  fn issued_vouchers_keep_their_amount() {
+    let issued = IssuedVoucher {
+      nonce: 42,
+      amount: 1500,
+    };
+
+    assert_eq!(issued.encode(), "42:1500");
+    assert_eq!(IssuedVoucher::decode("42:1500").unwrap(), issued);
+    assert!(IssuedVoucher::decode("42").is_err());
+  }
+}
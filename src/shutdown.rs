@@ -0,0 +1,47 @@
+use std::{
+  sync::OnceLock,
+  time::{Duration, Instant},
+};
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+// Whether a shutdown signal was received, readiness fails from then on to move traffic elsewhere.
+pub fn started() -> bool {
+  STARTED.get().is_some()
+}
+
+// What is left of `timeout` since the signal, one budget shared by every phase of the shutdown.
+pub fn remaining(timeout: Duration) -> Duration {
+  match STARTED.get() {
+    Some(started) => timeout.saturating_sub(started.elapsed()),
+    None => timeout,
+  }
+}
+
+// Resolves on SIGTERM, which Kubernetes sends before killing the pod, or on ctrl-c.
+pub async fn signal() {
+  let ctrl_c = async {
+    tokio::signal::ctrl_c()
+      .await
+      .expect("listening for ctrl-c was wrong");
+  };
+
+  #[cfg(unix)]
+  let terminate = async {
+    tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+      .expect("listening for SIGTERM was wrong")
+      .recv()
+      .await;
+  };
+
+  #[cfg(not(unix))]
+  let terminate = std::future::pending::<()>();
+
+  tokio::select! {
+    _ = ctrl_c => {},
+    _ = terminate => {},
+  }
+
+  STARTED.get_or_init(Instant::now);
+  tracing::info!("shutdown signal received");
+}
@@ -0,0 +1,45 @@
+pub mod local;
+pub mod s3;
+
+use crate::config::StorageConfig;
+use anyhow::Result;
+use axum::Router;
+use std::sync::Arc;
+use tower_http::services::ServeDir;
+
+#[axum::async_trait]
+pub trait FileStorage: Send + Sync {
+  // Stores the object under `key` and returns the public url it can be fetched from.
+  async fn put(&self, key: &str, content_type: &str, bytes: Vec<u8>) -> Result<String>;
+}
+
+// File extension of the image formats we accept for uploads.
+pub fn image_extension(content_type: &str) -> Option<&'static str> {
+  match content_type {
+    "image/png" => Some("png"),
+    "image/jpeg" => Some("jpg"),
+    "image/webp" => Some("webp"),
+    "image/gif" => Some("gif"),
+    _ => None,
+  }
+}
+
+pub fn from_config(config: &StorageConfig) -> Result<Arc<dyn FileStorage>> {
+  match config {
+    StorageConfig::Local(config) => Ok(Arc::new(local::LocalStorage::new(config))),
+    StorageConfig::S3(config) => Ok(Arc::new(s3::S3Storage::new(config)?)),
+  }
+}
+
+// Serves the files of the local storage at the path of their public url, objects in S3 are served by S3.
+pub fn router<S>(config: &StorageConfig) -> Router<S>
+where
+  S: Clone + Send + Sync + 'static,
+{
+  match config {
+    StorageConfig::Local(config) => {
+      Router::new().nest_service(&config.url_path, ServeDir::new(&config.root))
+    }
+    StorageConfig::S3(_) => Router::new(),
+  }
+}
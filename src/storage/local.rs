@@ -0,0 +1,32 @@
+use super::FileStorage;
+use crate::config::LocalStorageConfig;
+use anyhow::Result;
+use std::path::PathBuf;
+
+pub struct LocalStorage {
+  root: PathBuf,
+  public_url: String,
+}
+
+impl LocalStorage {
+  pub fn new(config: &LocalStorageConfig) -> Self {
+    Self {
+      root: config.root.to_owned(),
+      public_url: config.public_url.to_owned(),
+    }
+  }
+}
+
+#[axum::async_trait]
+impl FileStorage for LocalStorage {
+  async fn put(&self, key: &str, _content_type: &str, bytes: Vec<u8>) -> Result<String> {
+    let path = self.root.join(key);
+
+    if let Some(parent) = path.parent() {
+      tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(&path, bytes).await?;
+
+    Ok(format!("{}/{key}", self.public_url.trim_end_matches('/')))
+  }
+}
@@ -0,0 +1,104 @@
+use super::FileStorage;
+use crate::config::S3Config;
+use anyhow::{anyhow, bail, Result};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+// Any S3-compatible endpoint (AWS, MinIO, R2...) addressed path-style and signed with SigV4.
+pub struct S3Storage {
+  endpoint: surf::Url,
+  region: String,
+  bucket: String,
+  access_key: String,
+  secret_key: String,
+  public_url: String,
+}
+
+impl S3Storage {
+  pub fn new(config: &S3Config) -> Result<Self> {
+    Ok(Self {
+      endpoint: surf::Url::parse(&config.endpoint)?,
+      region: config.region.to_owned(),
+      bucket: config.bucket.to_owned(),
+      access_key: config.access_key.to_owned(),
+      secret_key: config.secret_key.to_owned(),
+      public_url: config.public_url.to_owned(),
+    })
+  }
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+  let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC can take key of any size");
+  mac.update(data.as_bytes());
+  mac.finalize().into_bytes().to_vec()
+}
+
+#[axum::async_trait]
+impl FileStorage for S3Storage {
+  async fn put(&self, key: &str, content_type: &str, bytes: Vec<u8>) -> Result<String> {
+    let now = Utc::now();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+
+    let host = match (self.endpoint.host_str(), self.endpoint.port()) {
+      (Some(host), Some(port)) => format!("{host}:{port}"),
+      (Some(host), None) => host.to_string(),
+      (None, _) => bail!("S3_ENDPOINT has no host"),
+    };
+    let canonical_uri = format!(
+      "{}/{}/{key}",
+      self.endpoint.path().trim_end_matches('/'),
+      self.bucket
+    );
+    let payload_hash = hex::encode(Sha256::digest(&bytes));
+
+    let signed_headers = "content-type;host;x-amz-content-sha256;x-amz-date";
+    let canonical_request = format!(
+      "PUT\n{canonical_uri}\n\ncontent-type:{content_type}\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{amz_date}\n\n{signed_headers}\n{payload_hash}"
+    );
+
+    let scope = format!("{date}/{}/s3/aws4_request", self.region);
+    let string_to_sign = format!(
+      "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+      hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let signing_key = ["s3", "aws4_request"].iter().fold(
+      hmac_sha256(
+        &hmac_sha256(format!("AWS4{}", self.secret_key).as_bytes(), &date),
+        &self.region,
+      ),
+      |key, part| hmac_sha256(&key, part),
+    );
+    let signature = hex::encode(hmac_sha256(&signing_key, &string_to_sign));
+
+    let mut url = self.endpoint.clone();
+    url.set_path(&canonical_uri);
+
+    let response = surf::put(url)
+      .header("Content-Type", content_type)
+      .header("Host", host.as_str())
+      .header("x-amz-content-sha256", payload_hash.as_str())
+      .header("x-amz-date", amz_date.as_str())
+      .header(
+        "Authorization",
+        format!(
+          "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+          self.access_key
+        ),
+      )
+      .body(bytes)
+      .await
+      .map_err(surf::Error::into_inner)?;
+
+    if !response.status().is_success() {
+      return Err(anyhow!(
+        "S3 responded with status {} for {key}",
+        response.status()
+      ));
+    }
+
+    Ok(format!("{}/{key}", self.public_url.trim_end_matches('/')))
+  }
+}
@@ -0,0 +1,134 @@
+use crate::config::{Config, LogFormat};
+use crate::intercept::{request_id::REQUEST_ID_HEADER, sercurity::bearer_token};
+use crate::AppState;
+use axum::{
+  extract::{MatchedPath, State},
+  http::{header, HeaderMap, Request},
+  middleware::Next,
+  response::Response,
+  routing::get,
+  Router,
+};
+use error::AuthError;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use std::time::Instant;
+use subtle::ConstantTimeEq;
+use tower_http::{
+  classify::{ServerErrorsAsFailures, SharedClassifier},
+  trace::{DefaultOnFailure, DefaultOnResponse, MakeSpan, TraceLayer},
+};
+use tracing::{Level, Span};
+use tracing_subscriber::EnvFilter;
+
+pub fn init(config: &Config) {
+  let subscriber = tracing_subscriber::fmt().with_env_filter(EnvFilter::new(&config.log_filter));
+
+  match config.log_format {
+    LogFormat::Json => subscriber.json().with_current_span(true).init(),
+    LogFormat::Pretty => subscriber.pretty().init(),
+  }
+}
+
+// One span per request carrying the request id, so every event logged while handling it can be correlated.
+// Headers are left out on purpose, `Authorization` carries bearer tokens.
+#[derive(Clone)]
+pub struct RequestSpan;
+
+impl<B> MakeSpan<B> for RequestSpan {
+  fn make_span(&mut self, request: &Request<B>) -> Span {
+    let header_value = |name: &str| {
+      request
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+    };
+
+    tracing::info_span!(
+      "request",
+      request_id = header_value(REQUEST_ID_HEADER),
+      method = %request.method(),
+      path = request.uri().path(),
+      user_agent = header_value(header::USER_AGENT.as_str()),
+    )
+  }
+}
+
+// Seconds, from a cache hit to a slow upstream call.
+const DURATION_BUCKETS: [f64; 12] = [
+  0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0,
+];
+
+// Every `*_duration_seconds` histogram is exported with the same buckets so that they can be compared.
+pub fn install_metrics_recorder() -> anyhow::Result<PrometheusHandle> {
+  Ok(
+    PrometheusBuilder::new()
+      .set_buckets_for_metric(
+        Matcher::Suffix("_duration_seconds".to_string()),
+        &DURATION_BUCKETS,
+      )?
+      .install_recorder()?,
+  )
+}
+
+// Labelled by the route template rather than the raw path, `/campaigns/:id/claim` is a single series.
+// Must be added with `route_layer`, `MatchedPath` is only set once a route matched.
+pub async fn track_http<B>(request: Request<B>, next: Next<B>) -> Response {
+  let started = Instant::now();
+  let method = request.method().to_string();
+  let path = request
+    .extensions()
+    .get::<MatchedPath>()
+    .map(|path| path.as_str().to_string())
+    .unwrap_or_default();
+
+  let response = next.run(request).await;
+
+  let status = response.status().as_u16().to_string();
+  metrics::increment_counter!(
+    "http_requests_total",
+    "method" => method.clone(),
+    "path" => path.clone(),
+    "status" => status.clone(),
+  );
+  metrics::histogram!(
+    "http_request_duration_seconds",
+    started.elapsed().as_secs_f64(),
+    "method" => method,
+    "path" => path,
+    "status" => status,
+  );
+
+  response
+}
+
+async fn render_metrics(
+  State(state): State<AppState>,
+  headers: HeaderMap,
+) -> Result<String, AuthError> {
+  if let Some(metrics_token) = &state.config.metrics_token {
+    let token = headers
+      .get(header::AUTHORIZATION)
+      .and_then(bearer_token)
+      .ok_or(AuthError::MissingCredentials)?;
+
+    // Constant time, so that response timing does not reveal how much of a guess was right.
+    if !bool::from(token.as_bytes().ct_eq(metrics_token.as_bytes())) {
+      return Err(AuthError::WrongCredentials);
+    }
+  }
+
+  Ok(state.metrics.render())
+}
+
+// Scraped by Prometheus from inside the cluster, so it is neither documented nor reachable cross-origin.
+pub fn metrics_router() -> Router<AppState> {
+  Router::new().route("/metrics", get(render_metrics))
+}
+
+pub fn trace_layer() -> TraceLayer<SharedClassifier<ServerErrorsAsFailures>, RequestSpan> {
+  TraceLayer::new_for_http()
+    .make_span_with(RequestSpan)
+    .on_response(DefaultOnResponse::new().level(Level::INFO))
+    .on_failure(DefaultOnFailure::new().level(Level::ERROR))
+}
@@ -1,3 +1,26 @@
+use ethers::core::rand::{distributions::Alphanumeric, thread_rng, Rng};
+
 pub fn refresh_token_generate(user_id: i32) -> String {
   format!("refresh_token_{}", user_id)
 }
+
+pub fn validate_txn_hash(txn_hash: &str) -> Result<(), validator::ValidationError> {
+  let is_hex = txn_hash
+    .strip_prefix("0x")
+    .map(|hash| hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()))
+    .unwrap_or(false);
+
+  if is_hex {
+    Ok(())
+  } else {
+    Err(validator::ValidationError::new("invalid_txn_hash"))
+  }
+}
+
+pub fn random_string(len: usize) -> String {
+  thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(len)
+    .map(char::from)
+    .collect()
+}